@@ -0,0 +1,148 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use device_info::DeviceInfo;
+use error::LogictlError;
+use profile::{ButtonBinding, OnboardProfile, ProfileDirectory, DIRECTORY_SECTOR};
+
+// bumped whenever the archive layout changes
+pub const ARCHIVE_VERSION: u32 = 1;
+
+
+/**
+ * Which device the archive was taken from
+ */
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    pub product_id: u16,
+    pub model_id: Option<String>,
+    pub firmware: Option<String>,
+    pub serial: Option<String>,
+    pub timestamp: u64
+}
+
+
+/**
+ * One sector as read, in hex
+ * sectors that were never written have no valid crc and are not restored
+ */
+#[derive(Serialize, Deserialize)]
+pub struct SectorDump {
+    pub sector: u16,
+    pub crc_valid: bool,
+    pub data: String
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    pub manifest: Manifest,
+    pub sectors: Vec<SectorDump>
+}
+
+
+impl Manifest {
+    pub fn new(info: &DeviceInfo) -> Manifest {
+        let timestamp = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs(),
+            Err(_) => 0
+        };
+
+        Manifest {
+            version: ARCHIVE_VERSION,
+            product_id: info.product_id,
+            model_id: info.model_id.clone(),
+            firmware: info.firmware_version(),
+            serial: info.serial.clone(),
+            timestamp: timestamp
+        }
+    }
+
+    /**
+     * Sectors are only meaningful to the same model running the same firmware
+     */
+    pub fn check_compatible(&self, info: &DeviceInfo) -> Result<(), LogictlError> {
+        if self.version != ARCHIVE_VERSION {
+            return Err(LogictlError::Validation(format!("archive version {} is not supported, expected {}", self.version, ARCHIVE_VERSION)));
+        }
+        if self.product_id != info.product_id || self.model_id != info.model_id {
+            return Err(LogictlError::Validation(format!("archive is from model {} ({:04x}), device is {} ({:04x})",
+                self.model_id.as_ref().map_or("unknown", |m| m.as_str()), self.product_id,
+                info.model_id.as_ref().map_or("unknown", |m| m.as_str()), info.product_id)));
+        }
+        let firmware = info.firmware_version();
+        if self.firmware != firmware {
+            return Err(LogictlError::Validation(format!("archive is from firmware {}, device runs {}",
+                self.firmware.as_ref().map_or("unknown", |f| f.as_str()),
+                firmware.as_ref().map_or("unknown", |f| f.as_str()))));
+        }
+        Ok(())
+    }
+}
+
+
+impl SectorDump {
+    pub fn new(sector: u16, bytes: &[u8], crc_valid: bool) -> SectorDump {
+        let mut data = String::with_capacity(bytes.len() * 2);
+        for b in bytes {
+            data.push_str(&format!("{:02x}", b));
+        }
+
+        SectorDump {
+            sector: sector,
+            crc_valid: crc_valid,
+            data: data
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, LogictlError> {
+        let text = self.data.as_bytes();
+        if text.len() % 2 != 0 {
+            return Err(LogictlError::Validation(format!("sector 0x{:04x} has an odd number of hex digits", self.sector)));
+        }
+
+        let mut bytes = Vec::with_capacity(text.len() / 2);
+        for pair in text.chunks(2) {
+            let digits = String::from_utf8_lossy(pair);
+            match u8::from_str_radix(&digits, 16) {
+                Ok(b) => bytes.push(b),
+                Err(_) => return Err(LogictlError::Validation(format!("sector 0x{:04x} has invalid hex '{}'", self.sector, digits)))
+            }
+        }
+        return Ok(bytes);
+    }
+}
+
+
+/**
+ * Writable sectors the directory points at
+ * slots can be moved to any sector, read-only 0x01xx sectors are left out
+ */
+pub fn profile_sectors(directory: &ProfileDirectory) -> Vec<u16> {
+    let mut sectors = Vec::new();
+    for entry in &directory.entries {
+        if entry.sector & 0xff00 == 0 && !sectors.contains(&entry.sector) {
+            sectors.push(entry.sector);
+        }
+    }
+    return sectors;
+}
+
+
+/**
+ * Sectors holding macros that any button of the profile points to
+ */
+pub fn macro_sectors(profile: &OnboardProfile, profile_sectors: &[u16], sectors: &mut Vec<u16>) {
+    for binding in profile.buttons.iter().chain(profile.shift_buttons.iter()) {
+        if let ButtonBinding::Macro { page, .. } = *binding {
+            let sector = page as u16;
+
+            // a zeroed binding reads as a macro in the directory sector
+            if sector == DIRECTORY_SECTOR || profile_sectors.contains(&sector) {
+                continue;
+            }
+            if !sectors.contains(&sector) {
+                sectors.push(sector);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use error::LogictlError;
+
+// every sector of onboard memory ends with a CRC-CCITT of the rest of it, stored big endian
+// the device refuses writes with a wrong crc, a sector failing the check on read
+// was never written or is damaged
+
+const CRC_INITIAL: u16 = 0xffff;
+const CRC_POLY: u16 = 0x1021;
+
+
+fn crc_update(mut crc: u16, data: u8) -> u16 {
+    crc ^= (data as u16) << 8;
+    for _ in 0..8 {
+        if crc & 0x8000 != 0 {
+            crc = (crc << 1) ^ CRC_POLY;
+        }
+        else {
+            crc <<= 1;
+        }
+    }
+    return crc;
+}
+
+
+/**
+ * The crc of everything but the last 2 bytes
+ */
+pub fn compute(bytes: &[u8]) -> u16 {
+    let mut crc = CRC_INITIAL;
+    for &b in &bytes[..bytes.len() - 2] {
+        crc = crc_update(crc, b);
+    }
+    return crc;
+}
+
+
+/**
+ * The crc held in the last 2 bytes
+ */
+pub fn stored(bytes: &[u8]) -> u16 {
+    let len = bytes.len();
+    ((bytes[len - 2] as u16) << 8) | bytes[len - 1] as u16
+}
+
+
+pub fn is_valid(bytes: &[u8]) -> bool {
+    compute(bytes) == stored(bytes)
+}
+
+
+/**
+ * Writes the crc into the last 2 bytes
+ */
+pub fn seal(bytes: &mut [u8]) {
+    let crc = compute(bytes);
+    let len = bytes.len();
+    bytes[len - 2] = (crc >> 8) as u8;
+    bytes[len - 1] = (crc & 0xff) as u8;
+}
+
+
+/**
+ * Fails with a sector corrupted error when the crc does not match
+ */
+pub fn verify(sector: u16, bytes: &[u8]) -> Result<(), LogictlError> {
+    let computed = compute(bytes);
+    let stored = stored(bytes);
+    if computed != stored {
+        return Err(LogictlError::SectorCorrupted {
+            sector: sector,
+            stored: stored,
+            computed: computed
+        });
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::LogictlError;
+    use super::*;
+
+    #[test]
+    fn matches_crc_ccitt() {
+        // the standard check value for CRC-16/CCITT-FALSE
+        assert_eq!(compute(b"123456789\0\0"), 0x29b1);
+        assert_eq!(compute(&[0u8; 2]), CRC_INITIAL);
+    }
+
+    #[test]
+    fn seal_then_verify() {
+        let mut bytes = [0x5au8; 256];
+        seal(&mut bytes);
+        assert_eq!(stored(&bytes), compute(&bytes));
+        assert!(is_valid(&bytes));
+        assert!(verify(0x0001, &bytes).is_ok());
+
+        bytes[100] ^= 0x01;
+        assert!(!is_valid(&bytes));
+        match verify(0x0001, &bytes) {
+            Err(LogictlError::SectorCorrupted { sector, stored: crc, computed }) => {
+                assert_eq!(sector, 0x0001);
+                assert_eq!(crc, stored(&bytes));
+                assert_eq!(computed, compute(&bytes));
+            },
+            other => panic!("expected a corrupted sector, got {:?}", other)
+        }
+    }
+}
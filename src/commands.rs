@@ -0,0 +1,405 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use serde_json;
+use controller::UsbController;
+use dpi::DpiList;
+use error::LogictlError;
+use features;
+use hidpp::ProtocolVersion;
+use checksum;
+use profile;
+use profile::{OnboardMode, OnboardProfile, ProfileDirectory};
+use backup;
+use backup::{Archive, Manifest, SectorDump};
+use transport::Transport;
+
+/**
+ * One line of the features listing
+ */
+#[derive(Serialize)]
+struct FeatureEntry {
+    index: u8,
+    id: u16,
+    name: &'static str,
+    version: u8,
+    obsolete: bool,
+    hidden: bool,
+    engineering: bool
+}
+
+
+/**
+ * Lists every feature the device exposes
+ */
+pub fn print_features<T: Transport>(controller: &mut UsbController<T>, json: bool) -> Result<(), LogictlError> {
+    let mut entries = Vec::new();
+    for (id, info) in controller.list_features()? {
+        entries.push(FeatureEntry {
+            index: info.index,
+            id: id,
+            name: features::feature_name(id),
+            version: info.version,
+            obsolete: info.flags & features::FLAG_OBSOLETE != 0,
+            hidden: info.flags & features::FLAG_HIDDEN != 0,
+            engineering: info.flags & features::FLAG_ENGINEERING != 0
+        });
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    println!("Index  Feature  Version  Name");
+    for entry in &entries {
+        let mut flags = Vec::new();
+        if entry.obsolete {
+            flags.push("obsolete");
+        }
+        if entry.hidden {
+            flags.push("hidden");
+        }
+        if entry.engineering {
+            flags.push("engineering");
+        }
+
+        if flags.is_empty() {
+            println!("0x{:02x}   0x{:04x}   {:>7}  {}", entry.index, entry.id, entry.version, entry.name);
+        }
+        else {
+            println!("0x{:02x}   0x{:04x}   {:>7}  {} ({})", entry.index, entry.id, entry.version, entry.name, flags.join(", "));
+        }
+    }
+    Ok(())
+}
+
+
+/**
+ * Prints name, type, firmware versions and ids
+ */
+pub fn print_info<T: Transport>(controller: &mut UsbController<T>, vendor_id: u16, product_id: u16, json: bool) -> Result<(), LogictlError> {
+    let info = controller.read_device_info(vendor_id, product_id)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!("Name: {}", info.name);
+    println!("Type: {}", info.device_type);
+    println!("USB ID: {:04x}:{:04x}", info.vendor_id, info.product_id);
+    println!("Protocol: HID++ {}", info.protocol);
+    if let Some(ref unit_id) = info.unit_id {
+        println!("Unit ID: {}", unit_id);
+    }
+    if let Some(ref model_id) = info.model_id {
+        println!("Model ID: {}", model_id);
+    }
+    if let Some(ref serial) = info.serial {
+        println!("Serial: {}", serial);
+    }
+    for entity in &info.firmware {
+        let active = if entity.active { " (active)" } else { "" };
+        if entity.kind == "hardware" {
+            println!("Hardware: revision {}", entity.version);
+        }
+        else {
+            println!("Firmware {}: {} {} build {:04x}{}", entity.kind, entity.name, entity.version, entity.build, active);
+        }
+    }
+    Ok(())
+}
+
+
+/**
+ * Reads a profile sector and saves it as it was read,
+ * optionally with a decoded json copy next to it
+ */
+pub fn dump_profile<T: Transport>(controller: &mut UsbController<T>, profile_number: u8, output: &str, json_output: Option<&str>) -> Result<(), LogictlError> {
+    let sector = controller.profile_sector(profile_number)?;
+    let bytes = controller.read_sector(sector)?;
+    let decoded = OnboardProfile::parse(&bytes)?;
+
+    File::create(output)?.write_all(&bytes)?;
+    println!("Profile {} \"{}\" saved to {}", profile_number, decoded.name(), output);
+
+    if let Some(path) = json_output {
+        let mut file = File::create(path)?;
+        file.write_all(serde_json::to_string_pretty(&decoded)?.as_bytes())?;
+        file.write_all(b"\n")?;
+        println!("Decoded profile saved to {}", path);
+    }
+    Ok(())
+}
+
+
+/**
+ * Writes a saved profile sector back to the device, then reads it back to check
+ */
+pub fn restore_profile<T: Transport>(controller: &mut UsbController<T>, profile_number: u8, input: &str) -> Result<(), LogictlError> {
+    let mut bytes = Vec::new();
+    File::open(input)?.read_to_end(&mut bytes)?;
+
+    if bytes.len() != profile::SECTOR_SIZE {
+        return Err(LogictlError::Validation(format!("{} is {} bytes, a profile is {}", input, bytes.len(), profile::SECTOR_SIZE)));
+    }
+    let sector = controller.profile_sector(profile_number)?;
+    checksum::verify(sector, &bytes)?;
+    let decoded = OnboardProfile::parse(&bytes)?;
+
+    println!("Writing \"{}\" to profile {}", decoded.name(), profile_number);
+    let counter = controller.read_write_counter(sector)?;
+    let written = controller.write_profile_sector(sector, &bytes, counter)?;
+
+    // only the write counter and crc may differ from the file
+    let read_back = controller.read_sector(sector)?;
+    if read_back != written {
+        return Err(LogictlError::from(io::Error::new(io::ErrorKind::InvalidData,
+            format!("profile {} does not match {} after writing", profile_number, input))));
+    }
+    println!("Profile {} restored and verified", profile_number);
+    Ok(())
+}
+
+
+/**
+ * Saves the profile directory, the five profiles and the macros they use
+ * into one archive along with what device they came from
+ */
+pub fn backup<T: Transport>(controller: &mut UsbController<T>, vendor_id: u16, product_id: u16, output: &str) -> Result<(), LogictlError> {
+    let info = controller.read_device_info(vendor_id, product_id)?;
+    let mut sectors = Vec::new();
+
+    let directory = controller.read_sector(profile::DIRECTORY_SECTOR)?;
+    sectors.push(SectorDump::new(profile::DIRECTORY_SECTOR, &directory, true));
+    let profiles = backup::profile_sectors(&ProfileDirectory::parse(&directory)?);
+
+    let mut macros = Vec::new();
+    for &sector in &profiles {
+        let bytes = controller.read_raw_sector(sector)?;
+        let crc_valid = checksum::is_valid(&bytes);
+        if crc_valid {
+            backup::macro_sectors(&OnboardProfile::parse(&bytes)?, &profiles, &mut macros);
+        }
+        else {
+            println!("Profile sector 0x{:04x} has no valid crc, it will not be restored", sector);
+        }
+        sectors.push(SectorDump::new(sector, &bytes, crc_valid));
+    }
+
+    for &sector in &macros {
+        let bytes = controller.read_sector(sector)?;
+        sectors.push(SectorDump::new(sector, &bytes, true));
+    }
+
+    let archive = Archive {
+        manifest: Manifest::new(&info),
+        sectors: sectors
+    };
+    let mut file = File::create(output)?;
+    file.write_all(serde_json::to_string_pretty(&archive)?.as_bytes())?;
+    file.write_all(b"\n")?;
+
+    println!("Saved {} sectors, {} with macros, to {}", archive.sectors.len(), macros.len(), output);
+    Ok(())
+}
+
+
+/**
+ * Writes back every valid sector of an archive, once it matches the device
+ * The directory goes last so it never points at a half written profile
+ */
+pub fn restore_all<T: Transport>(controller: &mut UsbController<T>, vendor_id: u16, product_id: u16, input: &str) -> Result<(), LogictlError> {
+    let mut text = String::new();
+    File::open(input)?.read_to_string(&mut text)?;
+    let archive: Archive = serde_json::from_str(&text)?;
+
+    let info = controller.read_device_info(vendor_id, product_id)?;
+    archive.manifest.check_compatible(&info)?;
+
+    // the archived directory says which sectors hold profiles
+    let profiles = match archive.sectors.iter().find(|dump| dump.sector == profile::DIRECTORY_SECTOR) {
+        Some(dump) => backup::profile_sectors(&ProfileDirectory::parse(&dump.bytes()?)?),
+        None => return Err(LogictlError::Validation("archive has no directory sector".to_string()))
+    };
+
+    // check every sector before writing any of them
+    let mut writes = Vec::new();
+    for dump in &archive.sectors {
+        let bytes = dump.bytes()?;
+        if bytes.len() != profile::SECTOR_SIZE {
+            return Err(LogictlError::Validation(format!("sector 0x{:04x} is {} bytes, expected {}", dump.sector, bytes.len(), profile::SECTOR_SIZE)));
+        }
+        if !dump.crc_valid {
+            continue;
+        }
+        checksum::verify(dump.sector, &bytes)?;
+        writes.push((dump.sector, bytes));
+    }
+    writes.sort_by_key(|&(sector, _)| sector == profile::DIRECTORY_SECTOR);
+
+    for &(sector, ref bytes) in &writes {
+        println!("Writing sector 0x{:04x}", sector);
+        let written = if profiles.contains(&sector) {
+            let counter = controller.read_write_counter(sector)?;
+            controller.write_profile_sector(sector, bytes, counter)?
+        }
+        else {
+            controller.write_sector(sector, bytes)?;
+            bytes.clone()
+        };
+
+        let read_back = controller.read_sector(sector)?;
+        if read_back != written {
+            return Err(LogictlError::from(io::Error::new(io::ErrorKind::InvalidData,
+                format!("sector 0x{:04x} does not match the archive after writing", sector))));
+        }
+    }
+    println!("Restored {} sectors from {}", writes.len(), input);
+    Ok(())
+}
+
+
+/**
+ * Prints each profile slot, the sector behind it and whether it is enabled
+ */
+pub fn print_directory<T: Transport>(controller: &mut UsbController<T>, json: bool) -> Result<(), LogictlError> {
+    let directory = controller.read_directory()?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&directory)?);
+        return Ok(());
+    }
+
+    println!("Slot  Sector  Enabled");
+    for (i, entry) in directory.entries.iter().enumerate() {
+        let rom = if entry.sector & 0xff00 != 0 { " (read-only)" } else { "" };
+        println!("{:>4}  0x{:04x}  {}{}", i + 1, entry.sector, if entry.enabled { "yes" } else { "no" }, rom);
+    }
+    Ok(())
+}
+
+
+/**
+ * Turns profile slots on or off in one directory write
+ */
+pub fn set_profiles_enabled<T: Transport>(controller: &mut UsbController<T>, slots: &[u8], enabled: bool) -> Result<(), LogictlError> {
+    let mut directory = controller.read_directory()?;
+    for &slot in slots {
+        directory.slot_mut(slot)?.enabled = enabled;
+    }
+
+    if !directory.entries.iter().any(|entry| entry.enabled) {
+        return Err(LogictlError::Validation("at least one profile must stay enabled".to_string()));
+    }
+    controller.write_directory(&directory)
+}
+
+
+/**
+ * Points a profile slot at another sector, e.g. 0x0101 for the first built in profile
+ */
+pub fn set_profile_sector<T: Transport>(controller: &mut UsbController<T>, slot: u8, sector: u16) -> Result<(), LogictlError> {
+    let mut directory = controller.read_directory()?;
+    directory.set_sector(slot, sector)?;
+    controller.write_directory(&directory)
+}
+
+
+/**
+ * Prints whether the device runs its stored profiles or takes live changes
+ */
+pub fn print_mode<T: Transport>(controller: &mut UsbController<T>) -> Result<(), LogictlError> {
+    match controller.onboard_mode()? {
+        Some(mode) => println!("Mode: {}", mode),
+        None => return Err(LogictlError::FeatureNotSupported(features::ONBOARD_PROFILES))
+    }
+    Ok(())
+}
+
+
+pub fn set_mode<T: Transport>(controller: &mut UsbController<T>, mode: OnboardMode) -> Result<(), LogictlError> {
+    controller.set_onboard_mode(mode)?;
+    println!("Mode: {}", mode);
+    Ok(())
+}
+
+
+/**
+ * The dpi list, None on HID++ 1.0 devices which have no way to tell
+ */
+fn dpi_list<T: Transport>(controller: &mut UsbController<T>) -> Result<Option<DpiList>, LogictlError> {
+    if controller.protocol_version()? == ProtocolVersion::Hidpp10 {
+        return Ok(None);
+    }
+    return Ok(Some(controller.dpi_list(0)?));
+}
+
+
+/**
+ * Prints the sensor's dpi and every dpi it can do
+ */
+pub fn print_dpi<T: Transport>(controller: &mut UsbController<T>) -> Result<(), LogictlError> {
+    let (current, default) = controller.current_dpi(0)?;
+    let list = dpi_list(controller)?;
+
+    println!("DPI: {} (default {})", current, default);
+    if let Some(list) = list {
+        println!("Supported: {}", list);
+    }
+    Ok(())
+}
+
+
+/**
+ * Sets the dpi live, refusing values the sensor cannot do
+ * HID++ 1.0 devices reject those themselves
+ */
+pub fn set_dpi<T: Transport>(controller: &mut UsbController<T>, dpi: u16) -> Result<(), LogictlError> {
+    if let Some(list) = dpi_list(controller)? {
+        if !list.supports(dpi) {
+            return Err(LogictlError::Validation(format!("dpi {} is not supported, valid values are {}", dpi, list)));
+        }
+    }
+
+    controller.warn_live_change("dpi")?;
+    controller.set_dpi(0, dpi)?;
+    println!("DPI: {}", dpi);
+    Ok(())
+}
+
+
+fn rate_list(rates: &[u16]) -> String {
+    let names: Vec<String> = rates.iter().map(|rate| format!("{}", rate)).collect();
+    names.join(", ")
+}
+
+
+/**
+ * Prints the report rate and every rate the device can do
+ */
+pub fn print_rate<T: Transport>(controller: &mut UsbController<T>) -> Result<(), LogictlError> {
+    let rate = controller.report_rate()?;
+    let rates = controller.report_rates()?;
+
+    println!("Report rate: {} hz", rate);
+    println!("Supported: {} hz", rate_list(&rates));
+    Ok(())
+}
+
+
+/**
+ * Sets the report rate live, refusing rates the device does not list
+ */
+pub fn set_rate<T: Transport>(controller: &mut UsbController<T>, hz: u16) -> Result<(), LogictlError> {
+    let rates = controller.report_rates()?;
+    if !rates.contains(&hz) {
+        return Err(LogictlError::Validation(format!("{} hz is not supported, valid rates are {} hz", hz, rate_list(&rates))));
+    }
+
+    controller.warn_live_change("report rate")?;
+    controller.set_report_rate(hz)?;
+    println!("Report rate: {} hz", hz);
+    Ok(())
+}
@@ -0,0 +1,1351 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant};
+use device_info;
+use dpi::DpiList;
+use device_info::{DeviceInfo, FirmwareEntity};
+use features;
+use features::{FeatureInfo, FeatureMap};
+use hidpp;
+use checksum;
+use profile::{rate_hz, rate_interval, OnboardMode, ProfileSettings, OnboardProfile, ProfileDirectory, DIRECTORY_SECTOR, ROM_SECTORS, SECTOR_SIZE, WRITE_COUNTER_OFFSET};
+use hidpp::{HidppError, HidppMessage, ProtocolVersion};
+use error::LogictlError;
+use transport::Transport;
+use session;
+use LedMode;
+
+// wired devices answer on 0xff
+pub const DEVICE_INDEX: u8 = 0xff;
+
+// tags our requests so their replies can be told apart
+pub const SWID: u8 = 0x0a;
+
+// how long to wait for the reply to a request
+const RESPONSE_TIMEOUT_MS: u64 = 2000;
+
+// unrelated reports kept while waiting for replies
+const MAX_QUEUED_EVENTS: usize = 32;
+
+// IRoot Ping echoes this back in its last param
+const PING_DATA: u8 = 0x5a;
+
+// HID++ 1.0 firmware information register
+const REGISTER_FIRMWARE: u8 = 0xf1;
+
+// HID++ 1.0 current dpi, long register of little endian x and y
+const REGISTER_DPI: u8 = 0x63;
+
+// HID++ 1.0 report interval in ms
+const REGISTER_REPORT_RATE: u8 = 0x64;
+
+// rates every HID++ 1.0 mouse takes, there is no register listing them
+const HIDPP10_REPORT_RATES: [u16; 4] = [1000, 500, 250, 125];
+
+/**
+ * The enabled dpi stages of a profile, with the active one in brackets
+ */
+fn dpi_stages(profile: &OnboardProfile, active: Option<u8>) -> String {
+    let mut stages = Vec::new();
+    for (i, &dpi) in profile.dpi.iter().enumerate() {
+        if dpi == 0 {
+            continue;
+        }
+        if active == Some(i as u8) {
+            stages.push(format!("[{}]", dpi));
+        }
+        else {
+            stages.push(format!("{}", dpi));
+        }
+    }
+    stages.join(" ")
+}
+
+
+/*
+ * Sends HID++ commands to the mouse over any transport
+ */
+pub struct UsbController<T: Transport> {
+    transport: T,
+    print_messages: bool,
+    events: VecDeque<HidppMessage>,
+    features: FeatureMap,
+    protocol: Option<ProtocolVersion>,
+    mode: Option<OnboardMode>,
+    mode_warned: bool
+}
+
+
+impl<T: Transport> UsbController<T> {
+    pub fn new(transport: T, print_messages: bool) -> UsbController<T> {
+        return UsbController {
+            transport: transport,
+            print_messages: print_messages,
+            events: VecDeque::new(),
+            features: FeatureMap::new(),
+            protocol: None,
+            mode: None,
+            mode_warned: false
+        }
+    }
+
+    pub fn claim(&mut self) -> io::Result<()> {
+        self.transport.claim()
+    }
+
+    pub fn release(&mut self) -> io::Result<()> {
+        self.transport.release()
+    }
+
+    /**
+     * Reads a single report, waiting at most timeout
+     */
+    fn read_response(&mut self, out: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let result = self.transport.read_report(out, timeout)?;
+        if self.print_messages {
+            print!("read {:03} bytes: [", result);
+            for b in 0..result {
+                print!("{:x}, ", out[b]);
+            }
+            println!("]");
+        }
+        return Ok(result);
+    }
+
+    /**
+     * Keeps reading until the reply to request arrives
+     * anything else received meanwhile is queued as an event
+     */
+    fn wait_response(&mut self, request: &HidppMessage) -> Result<HidppMessage, LogictlError> {
+        let deadline = Instant::now() + Duration::from_millis(RESPONSE_TIMEOUT_MS);
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(LogictlError::Timeout {
+                    feature_index: request.feature_index,
+                    function: request.function
+                });
+            }
+
+            let mut resp: [u8; 20] = [0; 20];
+            let len = self.read_response(&mut resp, deadline - now)?;
+            if len == 0 {
+                continue;
+            }
+
+            // not a HID++ report, nothing can be waiting on it
+            let message = match HidppMessage::parse(&resp[..len]) {
+                Ok(message) => message,
+                Err(_) => continue
+            };
+
+            if message.answers(request) {
+                if let Some(err) = message.error() {
+                    return Err(LogictlError::Protocol(err));
+                }
+                return Ok(message);
+            }
+            self.queue_event(message);
+        }
+    }
+
+    /**
+     * Keeps a report that did not answer any request
+     * the oldest are dropped once the queue is full
+     */
+    fn queue_event(&mut self, message: HidppMessage) {
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(message);
+    }
+
+    /**
+     * Takes the oldest report received outside of a request
+     */
+    pub fn next_event(&mut self) -> Option<HidppMessage> {
+        self.events.pop_front()
+    }
+
+    /**
+     * Sends a message and waits for its reply
+     */
+    fn request(&mut self, msg: &HidppMessage) -> Result<HidppMessage, LogictlError> {
+        let bytes = msg.encode()?;
+        if bytes.len() == 7 {
+            let mut buf: [u8; 7] = [0; 7];
+            buf.copy_from_slice(&bytes);
+            self.transport.send_short(&buf)?;
+        }
+        else {
+            let mut buf: [u8; 20] = [0; 20];
+            buf.copy_from_slice(&bytes);
+            self.transport.send_long(&buf)?;
+        }
+        self.wait_response(msg)
+    }
+
+    /**
+     * Pings the root feature the first time, the answer is kept for the session
+     * HID++ 1.0 devices reply with an invalid sub id error instead
+     */
+    pub fn protocol_version(&mut self) -> Result<ProtocolVersion, LogictlError> {
+        if let Some(protocol) = self.protocol {
+            return Ok(protocol);
+        }
+
+        let ping = HidppMessage::short(DEVICE_INDEX, 0x00, 0x1, SWID, &[0x00, 0x00, PING_DATA]);
+        let protocol = match self.request(&ping) {
+            Ok(resp) => {
+                if resp.param(2) != PING_DATA {
+                    eprintln!("Ping returned 0x{:02x}, expected 0x{:02x}", resp.param(2), PING_DATA);
+                }
+                ProtocolVersion::Hidpp20 { major: resp.param(0), minor: resp.param(1) }
+            },
+            Err(LogictlError::Protocol(HidppError::InvalidSubId)) => ProtocolVersion::Hidpp10,
+            Err(err) => return Err(err)
+        };
+
+        self.protocol = Some(protocol);
+        return Ok(protocol);
+    }
+
+    /**
+     * Reads a short register, params select what to read for some registers
+     */
+    pub fn read_register(&mut self, register: u8, params: &[u8]) -> Result<Vec<u8>, LogictlError> {
+        let resp = self.request(&HidppMessage::register(DEVICE_INDEX, hidpp::GET_REGISTER, register, params))?;
+        return Ok(resp.params);
+    }
+
+    /**
+     * Writes up to 3 bytes to a short register
+     */
+    pub fn write_register(&mut self, register: u8, params: &[u8]) -> Result<(), LogictlError> {
+        self.request(&HidppMessage::register(DEVICE_INDEX, hidpp::SET_REGISTER, register, params))?;
+        Ok(())
+    }
+
+    /**
+     * Reads all 16 bytes of a long register
+     */
+    pub fn read_long_register(&mut self, register: u8) -> Result<Vec<u8>, LogictlError> {
+        let resp = self.request(&HidppMessage::register(DEVICE_INDEX, hidpp::GET_LONG_REGISTER, register, &[]))?;
+        return Ok(resp.params);
+    }
+
+    /**
+     * Writes up to 16 bytes to a long register, the reply is a short report
+     */
+    pub fn write_long_register(&mut self, register: u8, params: &[u8]) -> Result<(), LogictlError> {
+        self.request(&HidppMessage::register(DEVICE_INDEX, hidpp::SET_LONG_REGISTER, register, params))?;
+        Ok(())
+    }
+
+    /**
+     * Looks up where a feature lives, asking the root feature the first time
+     * Ok(None) when the device does not have the feature,
+     * which is always the case for HID++ 1.0 devices
+     */
+    pub fn feature_info(&mut self, id: u16) -> Result<Option<FeatureInfo>, LogictlError> {
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            return Ok(None);
+        }
+        if let Some(info) = self.features.get(id) {
+            return Ok(info);
+        }
+
+        // IRoot GetFeature, index 0 means the feature is missing
+        let params = [(id >> 8) as u8, (id & 0xff) as u8];
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, 0x00, 0x0, SWID, &params))?;
+        let info = if resp.param(0) == 0x00 {
+            None
+        }
+        else {
+            Some(FeatureInfo {
+                index: resp.param(0),
+                flags: resp.param(1),
+                version: resp.param(2)
+            })
+        };
+
+        self.features.insert(id, info);
+        return Ok(info);
+    }
+
+    /**
+     * Index of a feature the command cannot do without
+     */
+    pub fn feature_index(&mut self, id: u16) -> Result<u8, LogictlError> {
+        match self.feature_info(id)? {
+            Some(info) => Ok(info.index),
+            None => Err(LogictlError::FeatureNotSupported(id))
+        }
+    }
+
+    /**
+     * Every feature the device has, in index order, through IFeatureSet
+     * The results also fill the feature map
+     */
+    pub fn list_features(&mut self) -> Result<Vec<(u16, FeatureInfo)>, LogictlError> {
+        let set_index = self.feature_index(features::FEATURE_SET)?;
+
+        // GetCount does not include the root feature
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, set_index, 0x0, SWID, &[]))?;
+        let count = resp.param(0);
+
+        let mut list = vec![(features::ROOT, FeatureInfo { index: 0x00, flags: 0x00, version: 0x00 })];
+        for index in 1..(count as u16 + 1) {
+            let index = index as u8;
+
+            // GetFeatureID
+            let resp = self.request(&HidppMessage::short(DEVICE_INDEX, set_index, 0x1, SWID, &[index]))?;
+            let id = ((resp.param(0) as u16) << 8) | resp.param(1) as u16;
+            let info = FeatureInfo {
+                index: index,
+                flags: resp.param(2),
+                version: resp.param(3)
+            };
+
+            self.features.insert(id, Some(info));
+            list.push((id, info));
+        }
+        return Ok(list);
+    }
+
+    /**
+     * Reads the name through Device Name & Type (0x0005)
+     * GetDeviceName returns up to 16 characters from an offset
+     */
+    pub fn device_name(&mut self) -> Result<String, LogictlError> {
+        let name_index = self.feature_index(features::DEVICE_NAME)?;
+
+        // GetDeviceNameCount
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, name_index, 0x0, SWID, &[]))?;
+        let len = resp.param(0) as usize;
+
+        let mut bytes = Vec::new();
+        while bytes.len() < len {
+            let resp = self.request(&HidppMessage::short(DEVICE_INDEX, name_index, 0x1, SWID, &[bytes.len() as u8]))?;
+            let chunk: Vec<u8> = resp.params.iter().cloned().take_while(|&b| b != 0).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            bytes.extend(chunk);
+        }
+        bytes.truncate(len);
+        return Ok(String::from_utf8_lossy(&bytes).into_owned());
+    }
+
+    /**
+     * Name, type, firmware versions and ids of the device
+     */
+    pub fn read_device_info(&mut self, vendor_id: u16, product_id: u16) -> Result<DeviceInfo, LogictlError> {
+        let protocol = self.protocol_version()?;
+        if protocol == ProtocolVersion::Hidpp10 {
+            return self.read_register_info(vendor_id, product_id);
+        }
+
+        let name = self.device_name()?;
+
+        // GetDeviceType
+        let name_index = self.feature_index(features::DEVICE_NAME)?;
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, name_index, 0x2, SWID, &[]))?;
+        let device_type = device_info::device_type_name(resp.param(0));
+
+        let mut info = DeviceInfo {
+            name: name,
+            device_type: device_type,
+            vendor_id: vendor_id,
+            product_id: product_id,
+            protocol: format!("{}", protocol),
+            unit_id: None,
+            model_id: None,
+            serial: None,
+            firmware: Vec::new()
+        };
+
+        let fw = match self.feature_info(features::DEVICE_FW_VERSION)? {
+            Some(fw) => fw,
+            None => return Ok(info)
+        };
+
+        // GetDeviceInfo: entity count, unit id x4, transport x2, model id x6
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, fw.index, 0x0, SWID, &[]))?;
+        let entity_count = resp.param(0);
+        info.unit_id = device_info::hex_id(&resp, 1, 4);
+        info.model_id = device_info::hex_id(&resp, 7, 6);
+
+        for entity in 0..entity_count {
+            let resp = self.request(&HidppMessage::short(DEVICE_INDEX, fw.index, 0x1, SWID, &[entity]))?;
+            info.firmware.push(device_info::parse_firmware_entity(&resp));
+        }
+
+        // GetSerialNumber arrived with version 4
+        if fw.version >= 4 {
+            let resp = self.request(&HidppMessage::short(DEVICE_INDEX, fw.index, 0x2, SWID, &[]))?;
+            let serial: String = resp.params.iter()
+                .take(12)
+                .take_while(|&&b| b != 0)
+                .map(|&b| b as char)
+                .collect();
+            if !serial.is_empty() {
+                info.serial = Some(serial);
+            }
+        }
+
+        return Ok(info);
+    }
+
+    /**
+     * HID++ 1.0 devices have no name feature, only the firmware register
+     * [0x01] version, [0x02] build and [0x04] bootloader version
+     */
+    fn read_register_info(&mut self, vendor_id: u16, product_id: u16) -> Result<DeviceInfo, LogictlError> {
+        let version = self.read_register(REGISTER_FIRMWARE, &[0x01])?;
+        let build = self.read_register(REGISTER_FIRMWARE, &[0x02])?;
+
+        let mut firmware = vec![FirmwareEntity {
+            kind: "firmware",
+            name: String::new(),
+            version: format!("{:02x}.{:02x}", version[1], version[2]),
+            build: ((build[1] as u16) << 8) | build[2] as u16,
+            active: true,
+            transport_pid: product_id
+        }];
+
+        // not every device has a bootloader entry
+        match self.read_register(REGISTER_FIRMWARE, &[0x04]) {
+            Ok(bootloader) => firmware.push(FirmwareEntity {
+                kind: "bootloader",
+                name: String::new(),
+                version: format!("{:02x}.{:02x}", bootloader[1], bootloader[2]),
+                build: 0,
+                active: false,
+                transport_pid: product_id
+            }),
+            Err(LogictlError::Protocol(_)) => {},
+            Err(err) => return Err(err)
+        }
+
+        return Ok(DeviceInfo {
+            name: String::new(),
+            device_type: "unknown",
+            vendor_id: vendor_id,
+            product_id: product_id,
+            protocol: format!("{}", ProtocolVersion::Hidpp10),
+            unit_id: None,
+            model_id: None,
+            serial: None,
+            firmware: firmware
+        });
+    }
+
+    /**
+     * Onboard profiles getMode, read once per session
+     * None when the device has no onboard profiles
+     */
+    pub fn onboard_mode(&mut self) -> Result<Option<OnboardMode>, LogictlError> {
+        if self.mode.is_some() {
+            return Ok(self.mode);
+        }
+        let feature_index = match self.feature_info(features::ONBOARD_PROFILES)? {
+            Some(info) => info.index,
+            None => return Ok(None)
+        };
+
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, feature_index, 0x2, SWID, &[]))?;
+        self.mode = Some(OnboardMode::from_byte(resp.param(0))?);
+        return Ok(self.mode);
+    }
+
+    /**
+     * Onboard profiles setMode
+     */
+    pub fn set_onboard_mode(&mut self, mode: OnboardMode) -> Result<(), LogictlError> {
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        self.request(&HidppMessage::short(DEVICE_INDEX, feature_index, 0x1, SWID, &[mode.to_byte()]))?;
+        self.mode = Some(mode);
+        Ok(())
+    }
+
+    /**
+     * Live changes fight the active profile in onboard mode
+     * and are gone on the next replug in host mode
+     */
+    pub fn warn_live_change(&mut self, what: &str) -> Result<(), LogictlError> {
+        match self.onboard_mode()? {
+            Some(OnboardMode::Onboard) =>
+                eprintln!("Warning: device is in onboard mode, the active profile may override the {} change, use `mode set host` first", what),
+            Some(OnboardMode::Host) =>
+                eprintln!("Warning: {} changed in host mode, it is not saved to any onboard profile", what),
+            None => {}
+        }
+        Ok(())
+    }
+
+    /**
+     * Profile memory is ignored while software is in charge, warns once per session
+     */
+    pub fn warn_profile_change(&mut self) -> Result<(), LogictlError> {
+        if self.mode_warned {
+            return Ok(());
+        }
+        if self.onboard_mode()? == Some(OnboardMode::Host) {
+            eprintln!("Warning: device is in host mode, profile changes only apply after `mode set onboard`");
+        }
+        self.mode_warned = true;
+        Ok(())
+    }
+
+    /**
+     * Which dpi slot of the active profile is in use, counting from 0
+     * Onboard profiles getCurrentDpiIndex
+     */
+    pub fn current_dpi_index(&mut self) -> Result<u8, LogictlError> {
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, feature_index, 0xb, SWID, &[]))?;
+        return Ok(resp.param(0));
+    }
+
+    /**
+     * Onboard profiles setCurrentDpiIndex
+     */
+    pub fn set_current_dpi_index(&mut self, index: u8) -> Result<(), LogictlError> {
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        self.request(&HidppMessage::short(DEVICE_INDEX, feature_index, 0xc, SWID, &[index]))?;
+        Ok(())
+    }
+
+    /**
+     * Makes stage 1-5 of the active profile's dpi list current,
+     * stages with a dpi of 0 are disabled and refused
+     */
+    pub fn set_dpi_stage(&mut self, stage: u8) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        let sector = self.send_4_status(device_index, feature_index, swid)?;
+        let profile = OnboardProfile::parse(&self.read_sector(sector)?)?;
+
+        let index = stage as usize - 1;
+        if index >= profile.dpi.len() || profile.dpi[index] == 0 {
+            return Err(LogictlError::Validation(format!("dpi stage {} is not enabled in the current profile, stages are {}",
+                stage, dpi_stages(&profile, None))));
+        }
+
+        self.set_current_dpi_index(index as u8)?;
+        println!("DPI stage {}: {}", stage, profile.dpi[index]);
+        Ok(())
+    }
+
+    /**
+     * Every dpi the sensor can do, Adjustable DPI GetSensorDpiList
+     */
+    pub fn dpi_list(&mut self, sensor: u8) -> Result<DpiList, LogictlError> {
+        let dpi_index = self.feature_index(features::ADJUSTABLE_DPI)?;
+
+        // GetSensorCount
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, dpi_index, 0x0, SWID, &[]))?;
+        if sensor >= resp.param(0) {
+            return Err(LogictlError::Validation(format!("sensor {} does not exist, the device has {}", sensor, resp.param(0))));
+        }
+
+        // [sensor, list of big endian values ...]
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, dpi_index, 0x1, SWID, &[sensor]))?;
+        let values: Vec<u16> = resp.params[1..].chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| ((pair[0] as u16) << 8) | pair[1] as u16)
+            .collect();
+        return Ok(DpiList::parse(&values));
+    }
+
+    /**
+     * The sensor's current and default dpi, GetSensorDpi
+     */
+    pub fn current_dpi(&mut self, sensor: u8) -> Result<(u16, u16), LogictlError> {
+
+        // the register has no default, report the current dpi for both
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            let resp = self.read_long_register(REGISTER_DPI)?;
+            let current = resp[0] as u16 | ((resp[1] as u16) << 8);
+            return Ok((current, current));
+        }
+
+        let dpi_index = self.feature_index(features::ADJUSTABLE_DPI)?;
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, dpi_index, 0x2, SWID, &[sensor]))?;
+        let current = ((resp.param(1) as u16) << 8) | resp.param(2) as u16;
+        let default = ((resp.param(3) as u16) << 8) | resp.param(4) as u16;
+        return Ok((current, default));
+    }
+
+    /**
+     * Changes the dpi straight away without touching profile memory, SetSensorDpi
+     */
+    pub fn set_dpi(&mut self, sensor: u8, dpi: u16) -> Result<(), LogictlError> {
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            let (low, high) = ((dpi & 0xff) as u8, (dpi >> 8) as u8);
+            return self.write_long_register(REGISTER_DPI, &[low, high, low, high]);
+        }
+
+        let dpi_index = self.feature_index(features::ADJUSTABLE_DPI)?;
+        let params = [sensor, (dpi >> 8) as u8, (dpi & 0xff) as u8];
+        self.request(&HidppMessage::short(DEVICE_INDEX, dpi_index, 0x3, SWID, &params))?;
+        Ok(())
+    }
+
+    /**
+     * Report rates the device can do in hz, fastest first, Report Rate GetReportRateList
+     * bit n of the list means an interval of n + 1 ms
+     */
+    pub fn report_rates(&mut self) -> Result<Vec<u16>, LogictlError> {
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            return Ok(HIDPP10_REPORT_RATES.to_vec());
+        }
+
+        let rate_index = self.feature_index(features::REPORT_RATE)?;
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, rate_index, 0x0, SWID, &[]))?;
+        let bitmap = resp.param(0);
+
+        let mut rates = Vec::new();
+        for bit in 0..8 {
+            if bitmap & (1 << bit) != 0 {
+                rates.push(rate_hz(bit + 1));
+            }
+        }
+        return Ok(rates);
+    }
+
+    /**
+     * The current report rate in hz, GetReportRate
+     */
+    pub fn report_rate(&mut self) -> Result<u16, LogictlError> {
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            let resp = self.read_register(REGISTER_REPORT_RATE, &[])?;
+            return Ok(rate_hz(resp[0]));
+        }
+
+        let rate_index = self.feature_index(features::REPORT_RATE)?;
+        let resp = self.request(&HidppMessage::short(DEVICE_INDEX, rate_index, 0x1, SWID, &[]))?;
+        return Ok(rate_hz(resp.param(0)));
+    }
+
+    /**
+     * Changes the report rate straight away, SetReportRate
+     */
+    pub fn set_report_rate(&mut self, hz: u16) -> Result<(), LogictlError> {
+        let interval = match rate_interval(hz) {
+            Some(interval) => interval,
+            None => return Err(LogictlError::Validation(format!("{} hz is not a whole number of ms", hz)))
+        };
+        if self.protocol_version()? == ProtocolVersion::Hidpp10 {
+            return self.write_register(REGISTER_REPORT_RATE, &[interval]);
+        }
+
+        let rate_index = self.feature_index(features::REPORT_RATE)?;
+        self.request(&HidppMessage::short(DEVICE_INDEX, rate_index, 0x2, SWID, &[interval]))?;
+        Ok(())
+    }
+
+    /**
+     * Reads a whole sector, failing when its crc does not match
+     */
+    pub fn read_sector(&mut self, sector: u16) -> Result<Vec<u8>, LogictlError> {
+        let bytes = self.read_raw_sector(sector)?;
+        checksum::verify(sector, &bytes)?;
+        return Ok(bytes);
+    }
+
+    /**
+     * Reads a whole sector of onboard memory as it is, 16 bytes per request
+     */
+    pub fn read_raw_sector(&mut self, sector: u16) -> Result<Vec<u8>, LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        let mut bytes = Vec::with_capacity(SECTOR_SIZE);
+        for offset in (0..SECTOR_SIZE).step_by(16) {
+            if session::interrupted() {
+                return Err(LogictlError::Interrupted);
+            }
+            let resp = self.send_5_read_record(device_index, feature_index, swid, (sector >> 8) as u8, (sector & 0xff) as u8, offset as u8)?;
+            for i in 0..16 {
+                bytes.push(resp.param(i));
+            }
+        }
+        return Ok(bytes);
+    }
+
+    /**
+     * Writes a whole sector of onboard memory in one start, data, end sequence
+     * The crc is computed here, whatever the last 2 bytes held is replaced
+     */
+    pub fn write_sector(&mut self, sector: u16, data: &[u8]) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        if data.len() != SECTOR_SIZE {
+            return Err(LogictlError::Validation(format!("sector must be {} bytes, got {}", SECTOR_SIZE, data.len())));
+        }
+        let mut bytes = data.to_vec();
+        checksum::seal(&mut bytes);
+
+        self.warn_profile_change()?;
+
+        // a started record is always finished, so only check before starting
+        if session::interrupted() {
+            return Err(LogictlError::Interrupted);
+        }
+
+        self.send_6_start_record(device_index, feature_index, swid, sector)?;
+        for chunk in bytes.chunks(16) {
+            let mut packet = [0u8; 16];
+            packet.copy_from_slice(chunk);
+            self.send_7_record(device_index, feature_index, swid, packet)?;
+        }
+        self.send_8_end_record(device_index, feature_index, swid)?;
+
+        Ok(())
+    }
+
+    /**
+     * The write counter a sector holds, reading only the record it is in
+     * an unwritten sector still has a counter, so there is no crc check
+     */
+    pub fn read_write_counter(&mut self, sector: u16) -> Result<u8, LogictlError> {
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let offset = WRITE_COUNTER_OFFSET - WRITE_COUNTER_OFFSET % 16;
+        let resp = self.send_5_read_record(DEVICE_INDEX, feature_index, SWID, (sector >> 8) as u8, (sector & 0xff) as u8, offset as u8)?;
+        return Ok(resp.param(WRITE_COUNTER_OFFSET % 16));
+    }
+
+    /**
+     * Writes a profile sector, the device only takes it when the write counter
+     * at byte 18 differs from the stored one, so it is set to the stored value + 1
+     * Returns the bytes as written
+     */
+    pub fn write_profile_sector(&mut self, sector: u16, data: &[u8], stored_counter: u8) -> Result<Vec<u8>, LogictlError> {
+        if data.len() != SECTOR_SIZE {
+            return Err(LogictlError::Validation(format!("sector must be {} bytes, got {}", SECTOR_SIZE, data.len())));
+        }
+
+        let mut bytes = data.to_vec();
+        bytes[WRITE_COUNTER_OFFSET] = stored_counter.wrapping_add(1);
+        checksum::seal(&mut bytes);
+
+        self.write_sector(sector, &bytes)?;
+        return Ok(bytes);
+    }
+
+    fn send_0_root(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) -> Result<HidppMessage, LogictlError> {
+        self.request(&HidppMessage::short(di, fi, 0x0, swid, &[offset, param_b, param_c]))
+    }
+
+    /**
+     * get feature data
+     * first param is an offset for large data
+     */
+    fn send_1_get_features(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) -> Result<HidppMessage, LogictlError> {
+        self.request(&HidppMessage::short(di, fi, 0x1, swid, &[offset, param_b, param_c]))
+    }
+
+    /**
+     * checks a feature is available?
+     * -- when param_b 0x00 it will read
+     * otherwise saves a key-value?
+     */
+    fn send_2_connected(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) -> Result<HidppMessage, LogictlError> {
+        self.request(&HidppMessage::short(di, fi, 0x2, swid, &[offset, param_b, param_c]))
+    }
+
+    /**
+     * Changes the Profile
+     * reports info when profile = 0x00
+     * changes led colors
+     */
+    fn send_3_profile(&mut self, di: u8, fi: u8, swid: u8, profile: u8) -> Result<HidppMessage, LogictlError> {
+
+        // the extra packets that follow are notifications, they get queued
+        self.request(&HidppMessage::short(di, fi, 0x3, swid, &[0x00, profile, 0x00]))
+    }
+
+
+    /**
+     * Param is unknown, usually set to 0x01
+     */
+    fn send_3_color(&mut self, di: u8, fi: u8, swid: u8, led_mode: u8, led_r: u8, led_g: u8, led_b: u8, cycle_a: u8, cycle_b: u8, cycle_c: u8, param: u8) -> Result<HidppMessage, LogictlError> {
+        let params: [u8; 10] = [
+            0x00, led_mode, led_r, led_g, led_b, param, 0x00, cycle_a, cycle_b, cycle_c
+        ];
+        self.request(&HidppMessage::long(di, fi, 0x3, swid, &params))
+    }
+
+    /**
+     * Returns the sector of the current profile
+     * 0x0001 to 0x0005, read-only profiles have 0x01 in the high byte
+     */
+    fn send_4_status(&mut self, di: u8, fi: u8, swid: u8) -> Result<u16, LogictlError> {
+        let resp = self.request(&HidppMessage::short(di, fi, 0x4, swid, &[]))?;
+        return Ok(((resp.param(0) as u16) << 8) | resp.param(1) as u16);
+    }
+
+    /**
+     * Reads the record 16 bytes at a time, starting at offset
+     * id_a and profile are the high and low bytes of the sector
+     */
+    fn send_5_read_record(&mut self, di: u8, fi: u8, swid: u8, id_a: u8, profile: u8, offset: u8) -> Result<HidppMessage, LogictlError> {
+        let params: [u8; 5] = [
+            id_a, profile, 0x00, offset, 0x01
+        ];
+        self.request(&HidppMessage::long(di, fi, 0x5, swid, &params))
+    }
+
+    /**
+     * Starts writing a whole sector, sector 0x01 for profile 1
+     * Sector 0x00 holds the list of enabled profiles
+     */
+    fn send_6_start_record(&mut self, di: u8, fi: u8, swid: u8, sector: u16) -> Result<HidppMessage, LogictlError> {
+        let params: [u8; 5] = [
+            (sector >> 8) as u8, (sector & 0xff) as u8, 0x00, 0x00, 0x01
+        ];
+        self.request(&HidppMessage::long(di, fi, 0x6, swid, &params))
+    }
+
+
+    fn send_7_record(&mut self, di: u8, fi: u8, swid: u8, params: [u8; 16]) -> Result<HidppMessage, LogictlError> {
+        self.request(&HidppMessage::long(di, fi, 0x7, swid, &params))
+    }
+
+    fn send_8_end_record(&mut self, di: u8, fi: u8, swid: u8) -> Result<HidppMessage, LogictlError> {
+
+        // the end of the command type 7 sequence
+        // the extra outputs after command 8 are queued as events
+        self.request(&HidppMessage::short(di, fi, 0x8, swid, &[]))
+    }
+
+    /*
+     * Runs when device is first connected
+     */
+    pub fn write_init_commands(&mut self) -> Result<(), LogictlError> {
+
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        // functions, sent in the high nibble of byte 3
+        // 0x0 root
+        // 0x1
+        // 0x2 check connected
+        // 0x3 color setting
+        // 0x4 get status, returns current profile
+        // 0x6, 0x7, 0x8 record start, data, end
+        // 0xb, 0xc get and set the current dpi index
+
+        // reset logic when device is first connected
+        self.send_1_get_features(device_index, 0x00, swid, 0x00, 0x03, 0x00)?;
+        self.send_1_get_features(device_index, 0x00, swid, 0x00, 0x03, 0x39)?;
+
+        let fw_index = self.feature_index(features::DEVICE_FW_VERSION)?;
+        self.send_0_root(device_index, fw_index, swid, 0x00, 0x00, 0x00)?;
+
+        self.send_1_get_features(device_index, fw_index, swid, 0x00, 0x00, 0x00)?;
+
+        let name_index = self.feature_index(features::DEVICE_NAME)?;
+
+        self.send_2_connected(device_index, name_index, swid, 0x00, 0x00, 0x00)?;
+
+        self.send_0_root(device_index, fw_index, swid, 0x00, 0x00, 0x00)?;
+        self.feature_info(features::BATTERY_VOLTAGE)?;
+        self.feature_info(features::ADC_MEASUREMENT)?;
+        self.feature_info(features::BATTERY_STATUS)?;
+
+        self.send_1_get_features(device_index, fw_index, swid, 0x00, 0x00, 0x00)?;
+
+        self.send_0_root(device_index, name_index, swid, 0x00, 0x00, 0x00)?;
+
+        // This returns response of device name string over 2 responses
+        // 0x10 is an offset
+        self.send_1_get_features(device_index, name_index, swid, 0x00, 0x00, 0x00)?;
+        self.send_1_get_features(device_index, name_index, swid, 0x10, 0x00, 0x00)?;
+
+        let spy_index = self.feature_index(features::MOUSE_BUTTON_SPY)?;
+        self.send_0_root(device_index, spy_index, swid, 0x00, 0x00, 0x00)?;
+        self.feature_info(features::MODE_STATUS)?;
+        self.feature_info(features::LED_CONTROL)?;
+
+        self.send_2_connected(device_index, feature_index, swid, 0x00, 0x00, 0x00)?;
+
+        let led_index = self.feature_index(features::COLOR_LED_EFFECTS)?;
+        self.send_0_root(device_index, led_index, swid, 0x00, 0x00, 0x00)?;
+
+        self.send_1_get_features(device_index, led_index, swid, 0x00, 0x00, 0x00)?;
+        self.send_2_connected(device_index, led_index, swid, 0x00, 0x00, 0x00)?;
+
+        // adds 0x01, 0x01 params
+        self.send_message(&HidppMessage::short(device_index, led_index, 0x8, swid, &[0x01, 0x01]))?;
+
+        self.send_4_status(device_index, feature_index, swid)?;
+
+        // set profile
+        self.switch_to_profile(0x01)?;
+
+        // select the 4th dpi stage
+        self.current_dpi_index()?;
+        self.set_current_dpi_index(0x03)?;
+
+        Ok(())
+    }
+
+    /**
+     * Sends a message without a dedicated helper, then prints the status
+     */
+    fn send_message(&mut self, msg: &HidppMessage) -> Result<(), LogictlError> {
+        println!("write {}", msg);
+        self.request(msg)?;
+        self.print_status()?;
+
+        Ok(())
+    }
+
+
+    /**
+     * Make sure no messages are queued to be read
+     */
+    pub fn clear_reads(&mut self) -> Result<(), LogictlError> {
+        let mut state = 1;
+        let mut resp: [u8; 20] = [0; 20];
+        while state > 0 {
+            state = self.read_response(&mut resp, Duration::from_millis(100))?;
+        }
+        self.events.clear();
+
+        Ok(())
+    }
+
+
+    /**
+     * Normally begins with 0x11, 0xff, (onboard profiles index), 0x4a ...
+     */
+    pub fn print_status(&mut self) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        let sector = self.send_4_status(device_index, feature_index, swid)?;
+        let read_only = if sector & 0xff00 != 0 { " (read-only)" } else { "" };
+
+        // sector 0 is the directory, no profile is active
+        if sector == DIRECTORY_SECTOR {
+            println!("Profile: none");
+        }
+        else {
+
+            // a corrupted sector would show a garbage name and dpi list
+            match self.read_sector(sector) {
+                Ok(bytes) => {
+                    let profile = OnboardProfile::parse(&bytes)?;
+                    println!("Profile {} \"{}\"{}", sector & 0xff, profile.name(), read_only);
+                    let index = self.current_dpi_index()?;
+                    println!("DPI: {}", dpi_stages(&profile, Some(index)));
+                },
+                Err(err @ LogictlError::SectorCorrupted { .. }) => {
+                    println!("Profile {}{}: {}", sector & 0xff, read_only, err);
+                },
+                Err(err) => return Err(err)
+            }
+        }
+        if let Some(mode) = self.onboard_mode()? {
+            println!("Mode: {}", mode);
+        }
+
+        // anything the device sent on its own since the last request
+        while let Some(event) = self.next_event() {
+            println!("Notification: {}", event);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * 0x01 to 0x05
+     */
+    pub fn switch_to_profile(&mut self, n: u8) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        self.warn_profile_change()?;
+        self.send_4_status(device_index, feature_index, swid)?;
+
+        self.send_3_profile(device_index, feature_index, swid, n)?;
+
+        self.send_4_status(device_index, feature_index, swid)?;
+
+        Ok(())
+    }
+
+    /**
+     * Reads the list of profile slots from sector 0
+     */
+    pub fn read_directory(&mut self) -> Result<ProfileDirectory, LogictlError> {
+        let bytes = self.read_sector(DIRECTORY_SECTOR)?;
+        ProfileDirectory::parse(&bytes)
+    }
+
+    /**
+     * The sector a profile slot points at, which need not be the slot number
+     */
+    pub fn profile_sector(&mut self, slot: u8) -> Result<u16, LogictlError> {
+        self.read_directory()?.sector(slot)
+    }
+
+    /**
+     * Writes the list of profile slots, with its crc, to sector 0
+     */
+    pub fn write_directory(&mut self, directory: &ProfileDirectory) -> Result<(), LogictlError> {
+        println!("Writing profile directory");
+        let bytes = directory.to_bytes()?;
+        self.write_sector(DIRECTORY_SECTOR, &bytes)
+    }
+
+    pub fn apply_color(&mut self, mode: &LedMode) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        self.send_4_status(device_index, feature_index, swid)?;
+
+        self.warn_live_change("color")?;
+
+        // only for the LED control
+        let led_index = self.feature_index(features::COLOR_LED_EFFECTS)?;
+        self.send_0_root(device_index, led_index, swid, 0x00, 0x00, 0x00)?;
+
+        let mut mode_id = 0x00;
+
+        // static mode params
+        let mut led_r = 0x00;
+        let mut led_g = 0x00;
+        let mut led_b = 0x00;
+
+        // cycle mode params (are 0x00 for other modes)
+        let mut cycle_a = 0x2a; // speed major
+        let mut cycle_b = 0xf8; // speed minor
+        let mut cycle_c = 0x64; // brightness
+        let mut unknown = 0x01;
+
+        match mode {
+            LedMode::Off => {
+                mode_id = 0x00;
+            }
+            LedMode::Static {r, g, b} => {
+                mode_id = 0x01;
+                led_r = *r;
+                led_g = *g;
+                led_b = *b;
+            },
+            LedMode::Cycle {speed, brightness} => {
+                mode_id = 0x02;
+                cycle_a = (*speed >> 8) as u8;
+                cycle_b = (*speed & 0xff) as u8;
+                cycle_c = *brightness;
+            }
+        }
+
+        self.send_3_color(device_index, led_index, swid, mode_id, led_r, led_g, led_b, cycle_a, cycle_b, cycle_c, unknown)?;
+
+        Ok(())
+    }
+
+    /**
+     * Reads the profile, changes only the given settings and writes it back
+     * Buttons, leds and anything else not asked for keep their stored values
+     */
+    pub fn apply_settings(&mut self, profile: u8, settings: &ProfileSettings) -> Result<(), LogictlError> {
+        let device_index = DEVICE_INDEX;
+        let feature_index = self.feature_index(features::ONBOARD_PROFILES)?;
+        let swid = SWID;
+
+        // without a profile edit the current one, as long as it is a user profile
+        let directory = self.read_directory()?;
+        let (use_profile, sector) = if profile > 0 {
+            (profile, directory.sector(profile)?)
+        }
+        else {
+            let current = self.send_4_status(device_index, feature_index, swid)?;
+            match directory.slot(current) {
+                Some(slot) if current & 0xff00 == 0 => (slot, current),
+                _ => return Err(LogictlError::Validation(format!("current profile sector 0x{:04x} cannot be edited, pass --profile {{1-5}}", current)))
+            }
+        };
+
+        println!("Setting profile {:02x}", use_profile);
+
+        // merge into what the device holds, a never written sector is erased flash
+        // and starts from the matching built in profile, anything else must pass the crc
+        let bytes = self.read_raw_sector(sector)?;
+        let mut current = if bytes.iter().all(|&b| b == 0xff) {
+            let rom_sector = ROM_SECTORS | use_profile as u16;
+            println!("Profile {} was never written, starting from built in sector 0x{:04x}", use_profile, rom_sector);
+            OnboardProfile::parse(&self.read_sector(rom_sector)?)?
+        }
+        else {
+            checksum::verify(sector, &bytes)?;
+            OnboardProfile::parse(&bytes)?
+        };
+
+        // the sensor may not do every dpi a profile can hold
+        if let Some(ref dpi) = settings.dpi {
+            if self.feature_info(features::ADJUSTABLE_DPI)?.is_some() {
+                let list = self.dpi_list(0)?;
+                if let Some(&bad) = dpi.iter().find(|&&value| !list.supports(value)) {
+                    return Err(LogictlError::Validation(format!("dpi {} is not supported, valid values are {}", bad, list)));
+                }
+            }
+        }
+        settings.apply(&mut current)?;
+
+        // the counter was read with the sector, no need to read it again
+        self.write_profile_sector(sector, &current.to_bytes(), bytes[WRITE_COUNTER_OFFSET])?;
+
+        // get final status
+        self.send_4_status(device_index, feature_index, swid)?;
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use hidpp::HidppError;
+    use transport::mock::MockTransport;
+    use super::*;
+
+    fn long_report(head: &[u8]) -> Vec<u8> {
+        let mut report = vec![0u8; 20];
+        report[..head.len()].copy_from_slice(head);
+        report
+    }
+
+    #[test]
+    fn sends_reports_through_the_transport() {
+        let ping = vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA];
+        let feature = vec![0x10, 0xff, 0x00, 0x0a, 0x0f, 0x00, 0x00];
+        let mode = vec![0x10, 0xff, 0x0f, 0x2a, 0x01, 0x00, 0x00];
+        let status = long_report(&[0x11, 0xff, 0x0f, 0x4a, 0x00, 0x01]);
+        let switched = vec![0x10, 0xff, 0x0f, 0x3a, 0x00, 0x03, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![ping, feature, mode, status.clone(), switched, status]), false);
+
+        controller.switch_to_profile(3).unwrap();
+        assert_eq!(controller.transport.sent, vec![
+            vec![0x10, 0xff, 0x00, 0x1a, 0x00, 0x00, PING_DATA],
+            vec![0x10, 0xff, 0x00, 0x0a, 0x81, 0x00, 0x00],
+            vec![0x10, 0xff, 0x0f, 0x2a, 0x00, 0x00, 0x00],
+            vec![0x10, 0xff, 0x0f, 0x4a, 0x00, 0x00, 0x00],
+            vec![0x10, 0xff, 0x0f, 0x3a, 0x00, 0x03, 0x00],
+            vec![0x10, 0xff, 0x0f, 0x4a, 0x00, 0x00, 0x00]
+        ]);
+    }
+
+    #[test]
+    fn claims_and_releases_the_transport() {
+        let mut controller = UsbController::new(MockTransport::new(vec![]), false);
+        controller.claim().unwrap();
+        controller.release().unwrap();
+        assert_eq!((controller.transport.claims.get(), controller.transport.releases.get()), (1, 1));
+    }
+
+    #[test]
+    fn clear_reads_drains_queued_reports() {
+        let reports = vec![vec![0x11, 0xff, 0x0e, 0x00], vec![0x11, 0xff, 0x0e, 0x10]];
+        let mut controller = UsbController::new(MockTransport::new(reports), false);
+        controller.clear_reads().unwrap();
+        assert!(controller.transport.reports.is_empty());
+    }
+
+    #[test]
+    fn queues_reports_that_do_not_answer() {
+        let notification = long_report(&[0x11, 0xff, 0x0b, 0x00, 0x02]);
+        let other_swid = vec![0x10, 0xff, 0x0b, 0x4b, 0x00, 0x02, 0x00];
+        let not_hidpp = vec![0x02, 0x01, 0x00, 0x00];
+        let reply = vec![0x10, 0xff, 0x0b, 0x4a, 0x00, 0x01, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![notification, other_swid, not_hidpp, reply]), false);
+
+        let request = HidppMessage::short(0xff, 0x0b, 0x4, 0xa, &[]);
+        let resp = controller.request(&request).unwrap();
+        assert_eq!(resp.params, vec![0x00, 0x01, 0x00]);
+        assert_eq!(controller.transport.sent, vec![vec![0x10, 0xff, 0x0b, 0x4a, 0x00, 0x00, 0x00]]);
+
+        let first = controller.next_event().unwrap();
+        assert_eq!((first.report_id, first.function, first.param(0)), (0x11, 0x0, 0x02));
+        let second = controller.next_event().unwrap();
+        assert_eq!(second.swid, 0xb);
+        assert!(controller.next_event().is_none());
+    }
+
+    #[test]
+    fn keeps_only_the_newest_events() {
+        let mut reports: Vec<Vec<u8>> = (0..MAX_QUEUED_EVENTS as u8 + 8)
+            .map(|i| long_report(&[0x11, 0xff, 0x0b, 0x00, i]))
+            .collect();
+        reports.push(vec![0x10, 0xff, 0x0b, 0x4a, 0x00, 0x00, 0x00]);
+        let mut controller = UsbController::new(MockTransport::new(reports), false);
+
+        controller.request(&HidppMessage::short(0xff, 0x0b, 0x4, 0xa, &[])).unwrap();
+        assert_eq!(controller.events.len(), MAX_QUEUED_EVENTS);
+        assert_eq!(controller.next_event().unwrap().param(0), 8);
+    }
+
+    #[test]
+    fn times_out_without_a_reply() {
+        let notification = long_report(&[0x11, 0xff, 0x0b, 0x00, 0x02]);
+        let mut controller = UsbController::new(MockTransport::new(vec![notification]), false);
+
+        match controller.request(&HidppMessage::short(0xff, 0x0b, 0x4, 0xa, &[])) {
+            Err(LogictlError::Timeout { feature_index: 0x0b, function: 0x4 }) => {},
+            other => panic!("expected a timeout, got {:?}", other)
+        }
+        assert_eq!(controller.events.len(), 1);
+    }
+
+    #[test]
+    fn error_replies_fail_the_request() {
+        let other_error = long_report(&[0x11, 0xff, 0xff, 0x0c, 0x4a, 0x02]);
+        let error = long_report(&[0x11, 0xff, 0xff, 0x0b, 0x4a, 0x05]);
+        let mut controller = UsbController::new(MockTransport::new(vec![other_error, error]), false);
+
+        match controller.request(&HidppMessage::short(0xff, 0x0b, 0x4, 0xa, &[])) {
+            Err(LogictlError::Protocol(HidppError::NotAllowed)) => {},
+            other => panic!("expected not allowed, got {:?}", other)
+        }
+        assert_eq!(controller.next_event().unwrap().error(), Some(HidppError::InvalidArgument));
+    }
+
+    #[test]
+    fn looks_features_up_once() {
+        let ping = vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA];
+        let found = vec![0x10, 0xff, 0x00, 0x0a, 0x0f, 0x00, 0x00];
+        let missing = vec![0x10, 0xff, 0x00, 0x0a, 0x00, 0x00, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![ping, found, missing]), false);
+
+        assert_eq!(controller.feature_index(features::ONBOARD_PROFILES).unwrap(), 0x0f);
+        assert_eq!(controller.feature_index(features::ONBOARD_PROFILES).unwrap(), 0x0f);
+        match controller.feature_index(features::ADJUSTABLE_DPI) {
+            Err(LogictlError::FeatureNotSupported(id)) => assert_eq!(id, features::ADJUSTABLE_DPI),
+            other => panic!("expected a missing feature, got {:?}", other)
+        }
+        assert_eq!(controller.transport.sent.len(), 3);
+    }
+
+    #[test]
+    fn hidpp10_devices_reject_the_ping() {
+        let error = vec![0x10, 0xff, 0x8f, 0x00, 0x1a, 0x01, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![error]), false);
+
+        assert_eq!(controller.protocol_version().unwrap(), ProtocolVersion::Hidpp10);
+        assert_eq!(controller.protocol_version().unwrap(), ProtocolVersion::Hidpp10);
+        assert_eq!(controller.transport.sent, vec![vec![0x10, 0xff, 0x00, 0x1a, 0x00, 0x00, PING_DATA]]);
+        assert!(controller.feature_info(features::ONBOARD_PROFILES).unwrap().is_none());
+    }
+
+    #[test]
+    fn hidpp20_devices_answer_the_ping() {
+        let reply = vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA];
+        let mut controller = UsbController::new(MockTransport::new(vec![reply]), false);
+
+        assert_eq!(controller.protocol_version().unwrap(), ProtocolVersion::Hidpp20 { major: 4, minor: 2 });
+    }
+
+    #[test]
+    fn accesses_registers() {
+        let written = vec![0x10, 0xff, 0x80, 0x64, 0x00, 0x00, 0x00];
+        let long = long_report(&[0x11, 0xff, 0x83, 0x63, 0x90, 0x01, 0x90, 0x01]);
+        let mut controller = UsbController::new(MockTransport::new(vec![written, long]), false);
+
+        controller.write_register(0x64, &[0x02]).unwrap();
+        assert_eq!(&controller.read_long_register(0x63).unwrap()[..4], &[0x90, 0x01, 0x90, 0x01]);
+        assert_eq!(controller.transport.sent, vec![
+            vec![0x10, 0xff, 0x80, 0x64, 0x02, 0x00, 0x00],
+            vec![0x10, 0xff, 0x83, 0x63, 0x00, 0x00, 0x00]
+        ]);
+    }
+
+    #[test]
+    fn reads_the_mode_once() {
+        let ping = vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA];
+        let feature = vec![0x10, 0xff, 0x00, 0x0a, 0x0f, 0x00, 0x00];
+        let mode = vec![0x10, 0xff, 0x0f, 0x2a, 0x02, 0x00, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![ping, feature, mode]), false);
+
+        assert_eq!(controller.onboard_mode().unwrap(), Some(OnboardMode::Host));
+        controller.warn_profile_change().unwrap();
+        controller.warn_live_change("dpi").unwrap();
+        assert_eq!(controller.transport.sent.len(), 3);
+    }
+
+    #[test]
+    fn hidpp10_devices_use_registers() {
+        let rejected = vec![0x10, 0xff, 0x8f, 0x00, 0x1a, 0x01, 0x00];
+        let rate = vec![0x10, 0xff, 0x81, 0x64, 0x02, 0x00, 0x00];
+        let rate_set = vec![0x10, 0xff, 0x80, 0x64, 0x00, 0x00, 0x00];
+        let dpi = long_report(&[0x11, 0xff, 0x83, 0x63, 0x20, 0x03, 0x20, 0x03]);
+        let dpi_set = vec![0x10, 0xff, 0x82, 0x63, 0x00, 0x00, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![rejected, rate, rate_set, dpi, dpi_set]), false);
+
+        assert_eq!(controller.report_rate().unwrap(), 500);
+        controller.set_report_rate(250).unwrap();
+        assert_eq!(controller.current_dpi(0).unwrap(), (800, 800));
+        controller.set_dpi(0, 1600).unwrap();
+
+        assert_eq!(&controller.transport.sent[1..4], &[
+            vec![0x10, 0xff, 0x81, 0x64, 0x00, 0x00, 0x00],
+            vec![0x10, 0xff, 0x80, 0x64, 0x04, 0x00, 0x00],
+            vec![0x10, 0xff, 0x83, 0x63, 0x00, 0x00, 0x00]
+        ]);
+        assert_eq!(&controller.transport.sent[4][..8], &[0x11, 0xff, 0x82, 0x63, 0x40, 0x06, 0x40, 0x06]);
+    }
+
+    #[test]
+    fn status_does_not_read_the_directory_as_a_profile() {
+        let ping = vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA];
+        let feature = vec![0x10, 0xff, 0x00, 0x0a, 0x0f, 0x00, 0x00];
+        let status = vec![0x10, 0xff, 0x0f, 0x4a, 0x00, 0x00, 0x00];
+        let mode = vec![0x10, 0xff, 0x0f, 0x2a, 0x02, 0x00, 0x00];
+        let mut controller = UsbController::new(MockTransport::new(vec![ping, feature, status, mode]), false);
+
+        controller.print_status().unwrap();
+        assert_eq!(controller.transport.sent.len(), 4);
+    }
+
+    #[test]
+    fn profile_writes_bump_the_given_counter() {
+        let mut replies = vec![
+            vec![0x10, 0xff, 0x00, 0x1a, 0x04, 0x02, PING_DATA],
+            vec![0x10, 0xff, 0x00, 0x0a, 0x0f, 0x00, 0x00],
+            long_report(&[0x11, 0xff, 0x0f, 0x5a, 0xff, 0x00, 0x1d, 0x00]),
+            vec![0x10, 0xff, 0x0f, 0x2a, 0x01, 0x00, 0x00],
+            long_report(&[0x11, 0xff, 0x0f, 0x6a])
+        ];
+        for _ in 0..SECTOR_SIZE / 16 {
+            replies.push(long_report(&[0x11, 0xff, 0x0f, 0x7a]));
+        }
+        replies.push(vec![0x10, 0xff, 0x0f, 0x8a, 0x00, 0x00, 0x00]);
+        let mut controller = UsbController::new(MockTransport::new(replies), false);
+
+        let counter = controller.read_write_counter(0x0001).unwrap();
+        assert_eq!(counter, 0x1d);
+        assert_eq!(&controller.transport.sent[2][..9], &[0x11, 0xff, 0x0f, 0x5a, 0x00, 0x01, 0x00, 0x10, 0x01]);
+
+        let written = controller.write_profile_sector(0x0001, &[0u8; SECTOR_SIZE], counter).unwrap();
+        assert_eq!(written[WRITE_COUNTER_OFFSET], 0x1e);
+        assert!(checksum::is_valid(&written));
+
+        // one read for the counter, none while writing
+        let reads = controller.transport.sent.iter().filter(|sent| sent[3] == 0x5a).count();
+        assert_eq!(reads, 1);
+    }
+}
@@ -0,0 +1,142 @@
+use hidpp::HidppMessage;
+
+/**
+ * One firmware entity from Device FW Version (0x0003) GetFwInfo
+ */
+#[derive(Serialize)]
+pub struct FirmwareEntity {
+    pub kind: &'static str,
+    pub name: String,
+    pub version: String,
+    pub build: u16,
+    pub active: bool,
+    pub transport_pid: u16
+}
+
+
+/**
+ * Everything the info command reports
+ */
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: &'static str,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub protocol: String,
+    pub unit_id: Option<String>,
+    pub model_id: Option<String>,
+    pub serial: Option<String>,
+    pub firmware: Vec<FirmwareEntity>
+}
+
+
+impl DeviceInfo {
+
+    /**
+     * Version of the running main firmware
+     */
+    pub fn firmware_version(&self) -> Option<String> {
+        self.firmware.iter()
+            .find(|entity| entity.kind == "firmware" && entity.active)
+            .or(self.firmware.iter().find(|entity| entity.kind == "firmware"))
+            .map(|entity| format!("{} {} build {:04x}", entity.name, entity.version, entity.build))
+    }
+}
+
+
+/**
+ * Device Name & Type (0x0005) GetDeviceType
+ */
+pub fn device_type_name(device_type: u8) -> &'static str {
+    match device_type {
+        0 => "keyboard",
+        1 => "remote control",
+        2 => "numpad",
+        3 => "mouse",
+        4 => "touchpad",
+        5 => "trackball",
+        6 => "presenter",
+        7 => "receiver",
+        8 => "headset",
+        9 => "webcam",
+        10 => "steering wheel",
+        11 => "joystick",
+        12 => "gamepad",
+        13 => "dock",
+        14 => "speaker",
+        15 => "microphone",
+        _ => "unknown"
+    }
+}
+
+
+fn firmware_kind_name(kind: u8) -> &'static str {
+    match kind {
+        0 => "firmware",
+        1 => "bootloader",
+        2 => "hardware",
+        3 => "touchpad",
+        4 => "optical sensor",
+        5 => "softdevice",
+        6 => "rf companion",
+        7 => "factory application",
+        8 => "rgb custom effect",
+        9 => "motor drive",
+        _ => "other"
+    }
+}
+
+
+/**
+ * Decodes a GetFwInfo reply
+ * [type, prefix x3, number (bcd), revision (bcd), build x2 (bcd), active, transport pid x2, ...]
+ */
+pub fn parse_firmware_entity(resp: &HidppMessage) -> FirmwareEntity {
+    let kind = resp.param(0) & 0x0f;
+
+    let mut name = String::new();
+    for i in 1..4 {
+        let c = resp.param(i);
+        if c.is_ascii_graphic() {
+            name.push(c as char);
+        }
+    }
+
+    // hardware entities only carry a revision number
+    let version = if kind == 2 {
+        format!("{}", resp.param(1))
+    }
+    else {
+        format!("{:02x}.{:02x}", resp.param(4), resp.param(5))
+    };
+
+    FirmwareEntity {
+        kind: firmware_kind_name(kind),
+        name: if kind == 2 { String::new() } else { name },
+        version: version,
+        build: ((resp.param(6) as u16) << 8) | resp.param(7) as u16,
+        active: resp.param(8) & 0x01 != 0,
+        transport_pid: ((resp.param(9) as u16) << 8) | resp.param(10) as u16
+    }
+}
+
+
+/**
+ * Hex string of params[start..start + len], None when all zero
+ */
+pub fn hex_id(resp: &HidppMessage, start: usize, len: usize) -> Option<String> {
+    let mut text = String::new();
+    let mut any = false;
+    for i in start..start + len {
+        let b = resp.param(i);
+        any = any || b != 0;
+        text.push_str(&format!("{:02x}", b));
+    }
+    if any {
+        Some(text)
+    }
+    else {
+        None
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+// list values with these top bits are a step between the values either side
+const STEP_MARKER: u16 = 0xe000;
+
+
+/**
+ * One part of a sensor's dpi list
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DpiSpec {
+    Value(u16),
+    Range { min: u16, max: u16, step: u16 }
+}
+
+
+/**
+ * Every dpi a sensor can do, from Adjustable DPI (0x2201) GetSensorDpiList
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpiList {
+    pub specs: Vec<DpiSpec>
+}
+
+
+impl DpiList {
+
+    /**
+     * Reads big endian values until a 0 ends the list
+     * [min, 0xe000 | step, max] describes a range
+     */
+    pub fn parse(values: &[u16]) -> DpiList {
+        let mut specs = Vec::new();
+        let mut i = 0;
+        while i < values.len() && values[i] != 0 {
+            let value = values[i];
+            if i + 2 < values.len() && values[i + 1] & STEP_MARKER == STEP_MARKER {
+                specs.push(DpiSpec::Range {
+                    min: value,
+                    max: values[i + 2],
+                    step: values[i + 1] & !STEP_MARKER
+                });
+                i += 3;
+            }
+            else {
+                specs.push(DpiSpec::Value(value));
+                i += 1;
+            }
+        }
+        return DpiList {
+            specs: specs
+        };
+    }
+
+    pub fn supports(&self, dpi: u16) -> bool {
+        self.specs.iter().any(|spec| match *spec {
+            DpiSpec::Value(value) => value == dpi,
+            DpiSpec::Range { min, max, step } =>
+                dpi >= min && dpi <= max && (step == 0 || (dpi - min) % step == 0)
+        })
+    }
+}
+
+
+impl fmt::Display for DpiList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, spec) in self.specs.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match *spec {
+                DpiSpec::Value(value) => write!(f, "{}", value)?,
+                DpiSpec::Range { min, max, step } => write!(f, "{}-{} in steps of {}", min, max, step)?
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values_until_zero() {
+        let list = DpiList::parse(&[400, 800, 1600, 0, 3200]);
+        assert_eq!(list.specs, vec![DpiSpec::Value(400), DpiSpec::Value(800), DpiSpec::Value(1600)]);
+        assert_eq!(format!("{}", list), "400, 800, 1600");
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let list = DpiList::parse(&[100, 0xe000 | 50, 12000, 16000, 0]);
+        assert_eq!(list.specs, vec![DpiSpec::Range { min: 100, max: 12000, step: 50 }, DpiSpec::Value(16000)]);
+        assert_eq!(format!("{}", list), "100-12000 in steps of 50, 16000");
+
+        assert!(list.supports(100));
+        assert!(list.supports(1650));
+        assert!(list.supports(12000));
+        assert!(list.supports(16000));
+        assert!(!list.supports(1625));
+        assert!(!list.supports(12050));
+        assert!(!list.supports(50));
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io;
+use libc;
+use libusb;
+use serde_json;
+use serde_json::error::Category;
+use hidpp::HidppError;
+
+/**
+ * Everything that can go wrong, each kind exits with its own code
+ * so scripts can tell a missing device from a bad argument
+ */
+#[derive(Debug)]
+pub enum LogictlError {
+    Parse(String),
+    Validation(String),
+    DeviceNotFound,
+    Permission(String),
+    Usb(libusb::Error),
+    Io(io::Error),
+    Timeout { feature_index: u8, function: u8 },
+    Protocol(HidppError),
+    FeatureNotSupported(u16),
+    SectorCorrupted { sector: u16, stored: u16, computed: u16 },
+    Interrupted
+}
+
+
+impl LogictlError {
+
+    /**
+     * Process exit code for this error
+     */
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            LogictlError::Parse(_) => 2,
+            LogictlError::Validation(_) => 3,
+            LogictlError::DeviceNotFound => 4,
+            LogictlError::Permission(_) => 5,
+            LogictlError::Usb(_) => 6,
+            LogictlError::Io(_) => 6,
+            LogictlError::Timeout { .. } => 7,
+            LogictlError::Protocol(_) => 8,
+            LogictlError::FeatureNotSupported(_) => 9,
+            LogictlError::SectorCorrupted { .. } => 10,
+            LogictlError::Interrupted => 130
+        }
+    }
+}
+
+
+impl fmt::Display for LogictlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogictlError::Parse(ref msg) =>
+                write!(f, "{}", msg),
+            LogictlError::Validation(ref msg) =>
+                write!(f, "{}", msg),
+            LogictlError::DeviceNotFound =>
+                write!(f, "device not found"),
+            LogictlError::Permission(ref msg) =>
+                write!(f, "permission denied: {}, try running as root or adding a udev rule", msg),
+            LogictlError::Usb(ref err) =>
+                write!(f, "usb error: {}", err),
+            LogictlError::Io(ref err) =>
+                write!(f, "io error: {}", err),
+            LogictlError::Timeout { feature_index, function } =>
+                write!(f, "no response to feature 0x{:02x} function 0x{:02x}", feature_index, function),
+            LogictlError::Protocol(err) =>
+                write!(f, "device replied with error: {}", err),
+            LogictlError::FeatureNotSupported(id) =>
+                write!(f, "device does not support feature 0x{:04x}", id),
+            LogictlError::SectorCorrupted { sector, stored, computed } =>
+                write!(f, "sector 0x{:04x} corrupted: stored crc {:04x}, computed {:04x}", sector, stored, computed),
+            LogictlError::Interrupted =>
+                write!(f, "interrupted")
+        }
+    }
+}
+
+
+impl From<HidppError> for LogictlError {
+    fn from(err: HidppError) -> LogictlError {
+        LogictlError::Protocol(err)
+    }
+}
+
+
+impl From<libusb::Error> for LogictlError {
+    fn from(err: libusb::Error) -> LogictlError {
+        match err {
+            libusb::Error::Access => LogictlError::Permission(format!("{}", err)),
+            libusb::Error::NoDevice => LogictlError::DeviceNotFound,
+            err => LogictlError::Usb(err)
+        }
+    }
+}
+
+
+/**
+ * libusb::Error is neither Copy nor Clone, rebuild it from a reference
+ */
+fn usb_error(err: &libusb::Error) -> libusb::Error {
+    match *err {
+        libusb::Error::Success => libusb::Error::Success,
+        libusb::Error::Io => libusb::Error::Io,
+        libusb::Error::InvalidParam => libusb::Error::InvalidParam,
+        libusb::Error::Access => libusb::Error::Access,
+        libusb::Error::NoDevice => libusb::Error::NoDevice,
+        libusb::Error::NotFound => libusb::Error::NotFound,
+        libusb::Error::Busy => libusb::Error::Busy,
+        libusb::Error::Timeout => libusb::Error::Timeout,
+        libusb::Error::Overflow => libusb::Error::Overflow,
+        libusb::Error::Pipe => libusb::Error::Pipe,
+        libusb::Error::Interrupted => libusb::Error::Interrupted,
+        libusb::Error::NoMem => libusb::Error::NoMem,
+        libusb::Error::NotSupported => libusb::Error::NotSupported,
+        libusb::Error::Other => libusb::Error::Other
+    }
+}
+
+
+impl From<io::Error> for LogictlError {
+    fn from(err: io::Error) -> LogictlError {
+
+        // transports wrap libusb errors, unwrap them to keep the detail
+        if let Some(usb_err) = err.get_ref().and_then(|e| e.downcast_ref::<libusb::Error>()) {
+            return LogictlError::from(usb_error(usb_err));
+        }
+        if err.raw_os_error() == Some(libc::ENODEV) {
+            return LogictlError::DeviceNotFound;
+        }
+
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => LogictlError::Permission(format!("{}", err)),
+            _ => LogictlError::Io(err)
+        }
+    }
+}
+
+
+/**
+ * Only a failed read or write is io, a malformed file is a parse error
+ */
+impl From<serde_json::Error> for LogictlError {
+    fn from(err: serde_json::Error) -> LogictlError {
+        match err.classify() {
+            Category::Io => LogictlError::from(io::Error::from(err)),
+            Category::Syntax | Category::Data | Category::Eof => LogictlError::Parse(format!("{}", err))
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use serde_json::Value;
+    use super::*;
+
+    #[test]
+    fn malformed_json_is_a_parse_error() {
+        for text in &["{\"sectors\": [", "{\"sectors\" 1}", "[1, 2] 3"] {
+            let err = LogictlError::from(serde_json::from_str::<Value>(text).unwrap_err());
+            match err {
+                LogictlError::Parse(_) => assert_eq!(err.exit_code(), 2),
+                other => panic!("expected a parse error for {}, got {:?}", text, other)
+            }
+        }
+
+        // well formed, but not what was asked for
+        let err = LogictlError::from(serde_json::from_str::<u8>("300").unwrap_err());
+        assert_eq!(err.exit_code(), 2);
+    }
+}
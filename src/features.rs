@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+
+// HID++ 2.0 feature ids
+pub const ROOT: u16 = 0x0000;
+pub const FEATURE_SET: u16 = 0x0001;
+pub const DEVICE_FW_VERSION: u16 = 0x0003;
+pub const DEVICE_NAME: u16 = 0x0005;
+pub const BATTERY_STATUS: u16 = 0x1000;
+pub const BATTERY_VOLTAGE: u16 = 0x1001;
+pub const LED_CONTROL: u16 = 0x1300;
+pub const ADC_MEASUREMENT: u16 = 0x1f20;
+pub const ADJUSTABLE_DPI: u16 = 0x2201;
+pub const REPORT_RATE: u16 = 0x8060;
+pub const COLOR_LED_EFFECTS: u16 = 0x8070;
+pub const MODE_STATUS: u16 = 0x8090;
+pub const ONBOARD_PROFILES: u16 = 0x8100;
+pub const MOUSE_BUTTON_SPY: u16 = 0x8110;
+
+
+/**
+ * Where a feature lives on this device, as returned by IRoot GetFeature
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureInfo {
+    pub index: u8,
+    pub flags: u8,
+    pub version: u8
+}
+
+
+/**
+ * Feature ids looked up so far this session
+ * None records a feature the device does not have, so it is not asked again
+ */
+pub struct FeatureMap {
+    features: HashMap<u16, Option<FeatureInfo>>
+}
+
+
+impl FeatureMap {
+    pub fn new() -> FeatureMap {
+        let mut features = HashMap::new();
+
+        // the root feature is always at index 0
+        features.insert(ROOT, Some(FeatureInfo { index: 0x00, flags: 0x00, version: 0x00 }));
+
+        return FeatureMap {
+            features: features
+        };
+    }
+
+    /**
+     * None when the feature has not been looked up yet
+     */
+    pub fn get(&self, id: u16) -> Option<Option<FeatureInfo>> {
+        self.features.get(&id).cloned()
+    }
+
+    pub fn insert(&mut self, id: u16, info: Option<FeatureInfo>) {
+        self.features.insert(id, info);
+    }
+}
+
+
+// flags returned with each feature
+pub const FLAG_OBSOLETE: u8 = 0x80;
+pub const FLAG_HIDDEN: u8 = 0x40;
+pub const FLAG_ENGINEERING: u8 = 0x20;
+
+
+/**
+ * Human readable name of a feature id
+ */
+pub fn feature_name(id: u16) -> &'static str {
+    match id {
+        0x0000 => "Root",
+        0x0001 => "Feature set",
+        0x0002 => "Feature info",
+        0x0003 => "Device firmware version",
+        0x0004 => "Device unit id",
+        0x0005 => "Device name and type",
+        0x0006 => "Device groups",
+        0x0007 => "Device friendly name",
+        0x0008 => "Keep alive",
+        0x0020 => "Config change",
+        0x0021 => "Crypto id",
+        0x0080 => "Target software",
+        0x0081 => "Wireless signal strength",
+        0x00c0 => "DFU control (legacy)",
+        0x00c1 => "DFU control (unsigned)",
+        0x00c2 => "DFU control (signed)",
+        0x00c3 => "DFU control",
+        0x00d0 => "DFU",
+        0x1000 => "Battery status",
+        0x1001 => "Battery voltage",
+        0x1004 => "Unified battery",
+        0x1010 => "Charging control",
+        0x1300 => "LED control",
+        0x1802 => "Device reset",
+        0x1803 => "GPIO access",
+        0x1805 => "OOB state",
+        0x1806 => "Configurable device properties",
+        0x1814 => "Change host",
+        0x1815 => "Hosts info",
+        0x1981 => "Backlight",
+        0x1982 => "Backlight 2",
+        0x1983 => "Backlight 3",
+        0x1a00 => "Presenter control",
+        0x1a01 => "3D sensor",
+        0x1b00 => "Reprogrammable keys",
+        0x1b04 => "Reprogrammable keys v4",
+        0x1bc0 => "Report HID usage",
+        0x1c00 => "Persistent remappable action",
+        0x1d4b => "Wireless device status",
+        0x1df0 => "Remaining pairings",
+        0x1f1f => "Firmware properties",
+        0x1f20 => "ADC measurement",
+        0x2001 => "Swap button cancel",
+        0x2005 => "Pointer axis orientation",
+        0x2100 => "Vertical scrolling",
+        0x2110 => "Smart shift",
+        0x2111 => "Smart shift enhanced",
+        0x2120 => "Hi-res scrolling",
+        0x2121 => "Hi-res wheel",
+        0x2130 => "Low-res wheel",
+        0x2150 => "Thumb wheel",
+        0x2200 => "Mouse pointer",
+        0x2201 => "Adjustable DPI",
+        0x2202 => "Extended adjustable DPI",
+        0x2205 => "Pointer speed",
+        0x2230 => "Angle snapping",
+        0x2240 => "Surface tuning",
+        0x2250 => "XY stats",
+        0x2251 => "Wheel stats",
+        0x2400 => "Hybrid tracking",
+        0x40a0 => "Fn inversion",
+        0x40a2 => "New fn inversion",
+        0x40a3 => "K375s fn inversion",
+        0x4100 => "Encryption",
+        0x4220 => "Lock key state",
+        0x4301 => "Solar dashboard",
+        0x4520 => "Keyboard layout",
+        0x4521 => "Keyboard disable keys",
+        0x4522 => "Keyboard disable by usage",
+        0x4530 => "Dual platform",
+        0x4531 => "Multi platform",
+        0x4540 => "Keyboard layout 2",
+        0x4600 => "Crown",
+        0x6010 => "Touchpad firmware items",
+        0x6011 => "Touchpad software items",
+        0x6012 => "Touchpad win8 firmware items",
+        0x6020 => "Tap enable",
+        0x6021 => "Tap enable extended",
+        0x6030 => "Cursor ballistic",
+        0x6040 => "Touchpad resolution",
+        0x6100 => "Touchpad raw XY",
+        0x6110 => "Touchmouse raw points",
+        0x6120 => "Touchmouse 6120",
+        0x6500 => "Gesture",
+        0x6501 => "Gesture 2",
+        0x8010 => "G keys",
+        0x8020 => "M keys",
+        0x8030 => "MR key",
+        0x8040 => "Brightness control",
+        0x8060 => "Report rate",
+        0x8061 => "Extended adjustable report rate",
+        0x8070 => "Color LED effects",
+        0x8071 => "RGB effects",
+        0x8080 => "Per key lighting",
+        0x8081 => "Per key lighting v2",
+        0x8090 => "Mode status",
+        0x8100 => "Onboard profiles",
+        0x8110 => "Mouse button spy",
+        0x8111 => "Latency monitoring",
+        0x8120 => "Gaming attachments",
+        0x8123 => "Force feedback",
+        0x8300 => "Sidetone",
+        0x8310 => "Equalizer",
+        0x8320 => "Headset out",
+        _ => "Unknown"
+    }
+}
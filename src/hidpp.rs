@@ -0,0 +1,405 @@
+use std::fmt;
+use error::LogictlError;
+
+pub const REPORT_SHORT: u8 = 0x10;
+pub const REPORT_LONG: u8 = 0x11;
+
+// replies with these in place of the feature index report an error
+pub const HIDPP20_ERROR: u8 = 0xff;
+pub const HIDPP10_ERROR: u8 = 0x8f;
+
+// HID++ 1.0 register access, sent in place of the feature index
+pub const SET_REGISTER: u8 = 0x80;
+pub const GET_REGISTER: u8 = 0x81;
+pub const SET_LONG_REGISTER: u8 = 0x82;
+pub const GET_LONG_REGISTER: u8 = 0x83;
+
+
+/**
+ * Protocol spoken by the device, found by pinging the root feature
+ * HID++ 1.0 devices reject the ping as an unknown sub id
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    Hidpp10,
+    Hidpp20 { major: u8, minor: u8 }
+}
+
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ProtocolVersion::Hidpp10 => write!(f, "1.0"),
+            ProtocolVersion::Hidpp20 { major, minor } => write!(f, "{}.{}", major, minor)
+        }
+    }
+}
+
+
+/**
+ * Error codes carried by HID++ error replies
+ * 2.0 errors come back with feature index 0xff, 1.0 errors with sub id 0x8f
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HidppError {
+    // HID++ 2.0
+    Unknown,
+    InvalidArgument,
+    OutOfRange,
+    HardwareError,
+    NotAllowed,
+    InvalidFeatureIndex,
+    InvalidFunctionId,
+    Busy,
+    Unsupported,
+
+    // HID++ 1.0
+    InvalidSubId,
+    InvalidAddress,
+    InvalidValue,
+    ConnectFail,
+    TooManyDevices,
+    AlreadyExists,
+    UnknownDevice,
+    ResourceError,
+    RequestUnavailable,
+    InvalidParamValue,
+    WrongPinCode,
+
+    // a code neither version defines
+    Other(u8)
+}
+
+
+impl HidppError {
+
+    pub fn from_hidpp20(code: u8) -> HidppError {
+        match code {
+            0x01 => HidppError::Unknown,
+            0x02 => HidppError::InvalidArgument,
+            0x03 => HidppError::OutOfRange,
+            0x04 => HidppError::HardwareError,
+            0x05 => HidppError::NotAllowed,
+            0x06 => HidppError::InvalidFeatureIndex,
+            0x07 => HidppError::InvalidFunctionId,
+            0x08 => HidppError::Busy,
+            0x09 => HidppError::Unsupported,
+            code => HidppError::Other(code)
+        }
+    }
+
+    pub fn from_hidpp10(code: u8) -> HidppError {
+        match code {
+            0x01 => HidppError::InvalidSubId,
+            0x02 => HidppError::InvalidAddress,
+            0x03 => HidppError::InvalidValue,
+            0x04 => HidppError::ConnectFail,
+            0x05 => HidppError::TooManyDevices,
+            0x06 => HidppError::AlreadyExists,
+            0x07 => HidppError::Busy,
+            0x08 => HidppError::UnknownDevice,
+            0x09 => HidppError::ResourceError,
+            0x0a => HidppError::RequestUnavailable,
+            0x0b => HidppError::InvalidParamValue,
+            0x0c => HidppError::WrongPinCode,
+            code => HidppError::Other(code)
+        }
+    }
+}
+
+
+impl fmt::Display for HidppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            HidppError::Unknown => "unknown error",
+            HidppError::InvalidArgument => "invalid argument",
+            HidppError::OutOfRange => "out of range",
+            HidppError::HardwareError => "hardware error",
+            HidppError::NotAllowed => "not allowed",
+            HidppError::InvalidFeatureIndex => "invalid feature index",
+            HidppError::InvalidFunctionId => "invalid function id",
+            HidppError::Busy => "busy",
+            HidppError::Unsupported => "unsupported",
+            HidppError::InvalidSubId => "invalid sub id",
+            HidppError::InvalidAddress => "invalid address",
+            HidppError::InvalidValue => "invalid value",
+            HidppError::ConnectFail => "connection failed",
+            HidppError::TooManyDevices => "too many devices",
+            HidppError::AlreadyExists => "already exists",
+            HidppError::UnknownDevice => "unknown device",
+            HidppError::ResourceError => "resource error",
+            HidppError::RequestUnavailable => "request unavailable",
+            HidppError::InvalidParamValue => "invalid parameter value",
+            HidppError::WrongPinCode => "wrong pin code",
+            HidppError::Other(code) => return write!(f, "error code 0x{:02x}", code)
+        };
+        write!(f, "{}", text)
+    }
+}
+
+
+/**
+ * A single HID++ report
+ * byte 3 holds the function in the high nibble and the software id in the low nibble
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidppMessage {
+    pub report_id: u8,
+    pub device_index: u8,
+    pub feature_index: u8,
+    pub function: u8,
+    pub swid: u8,
+    pub params: Vec<u8>
+}
+
+
+impl HidppMessage {
+
+    /**
+     * A 7 byte report with up to 3 params
+     */
+    pub fn short(device_index: u8, feature_index: u8, function: u8, swid: u8, params: &[u8]) -> HidppMessage {
+        HidppMessage {
+            report_id: REPORT_SHORT,
+            device_index: device_index,
+            feature_index: feature_index,
+            function: function,
+            swid: swid,
+            params: params.to_vec()
+        }
+    }
+
+    /**
+     * A 20 byte report with up to 16 params
+     */
+    pub fn long(device_index: u8, feature_index: u8, function: u8, swid: u8, params: &[u8]) -> HidppMessage {
+        HidppMessage {
+            report_id: REPORT_LONG,
+            device_index: device_index,
+            feature_index: feature_index,
+            function: function,
+            swid: swid,
+            params: params.to_vec()
+        }
+    }
+
+    /**
+     * A HID++ 1.0 register access, the register takes the place of byte 3
+     * only long register writes need the 20 byte report
+     */
+    pub fn register(device_index: u8, sub_id: u8, register: u8, params: &[u8]) -> HidppMessage {
+        HidppMessage {
+            report_id: if sub_id == SET_LONG_REGISTER { REPORT_LONG } else { REPORT_SHORT },
+            device_index: device_index,
+            feature_index: sub_id,
+            function: register >> 4,
+            swid: register & 0x0f,
+            params: params.to_vec()
+        }
+    }
+
+    /**
+     * Total report length including the 4 header bytes
+     */
+    pub fn report_len(report_id: u8) -> Option<usize> {
+        match report_id {
+            REPORT_SHORT => Some(7),
+            REPORT_LONG => Some(20),
+            _ => None
+        }
+    }
+
+    /**
+     * Builds the report bytes, params are padded with zeros
+     */
+    pub fn encode(&self) -> Result<Vec<u8>, LogictlError> {
+        let len = match HidppMessage::report_len(self.report_id) {
+            Some(len) => len,
+            None => return Err(LogictlError::Validation(format!("unknown report id 0x{:02x}", self.report_id)))
+        };
+        if self.function > 0x0f {
+            return Err(LogictlError::Validation(format!("function 0x{:x} does not fit in 4 bits", self.function)));
+        }
+        if self.swid > 0x0f {
+            return Err(LogictlError::Validation(format!("software id 0x{:x} does not fit in 4 bits", self.swid)));
+        }
+        if self.params.len() > len - 4 {
+            return Err(LogictlError::Validation(format!("{} params do not fit in a {} byte report", self.params.len(), len)));
+        }
+
+        let mut bytes = vec![0u8; len];
+        bytes[0] = self.report_id;
+        bytes[1] = self.device_index;
+        bytes[2] = self.feature_index;
+        bytes[3] = (self.function << 4) | self.swid;
+        bytes[4..4 + self.params.len()].copy_from_slice(&self.params);
+        return Ok(bytes);
+    }
+
+    /**
+     * Reads a received report, anything past the report length is ignored
+     */
+    pub fn parse(bytes: &[u8]) -> Result<HidppMessage, LogictlError> {
+        if bytes.is_empty() {
+            return Err(LogictlError::Validation("empty report".to_string()));
+        }
+        let len = match HidppMessage::report_len(bytes[0]) {
+            Some(len) => len,
+            None => return Err(LogictlError::Validation(format!("unknown report id 0x{:02x}", bytes[0])))
+        };
+        if bytes.len() < len {
+            return Err(LogictlError::Validation(format!("report 0x{:02x} needs {} bytes, got {}", bytes[0], len, bytes.len())));
+        }
+
+        return Ok(HidppMessage {
+            report_id: bytes[0],
+            device_index: bytes[1],
+            feature_index: bytes[2],
+            function: bytes[3] >> 4,
+            swid: bytes[3] & 0x0f,
+            params: bytes[4..len].to_vec()
+        });
+    }
+
+    /**
+     * Param at i, reading zero past the end
+     */
+    pub fn param(&self, i: usize) -> u8 {
+        match self.params.get(i) {
+            Some(&value) => value,
+            None => 0
+        }
+    }
+
+    /**
+     * The error carried by an error reply, None for anything else
+     * 2.0: [0x11, di, 0xff, fi, function/swid, code, ...]
+     * 1.0: [0x10, di, 0x8f, sub id, address, code, 0x00]
+     */
+    pub fn error(&self) -> Option<HidppError> {
+        if self.report_id == REPORT_LONG && self.feature_index == HIDPP20_ERROR {
+            return Some(HidppError::from_hidpp20(self.param(1)));
+        }
+        if self.report_id == REPORT_SHORT && self.feature_index == HIDPP10_ERROR {
+            return Some(HidppError::from_hidpp10(self.param(1)));
+        }
+        return None;
+    }
+
+    /**
+     * Checks whether this message answers the request
+     * The device index, feature index and function/swid byte must all match,
+     * error replies carry the request's feature index and function one byte later
+     */
+    pub fn answers(&self, request: &HidppMessage) -> bool {
+        if self.device_index != request.device_index {
+            return false;
+        }
+        let request_byte_3 = (request.function << 4) | request.swid;
+
+        if self.feature_index == request.feature_index && self.function == request.function && self.swid == request.swid {
+            return true;
+        }
+        if self.error().is_some() {
+            let byte_3 = (self.function << 4) | self.swid;
+            return byte_3 == request.feature_index && self.param(0) == request_byte_3;
+        }
+        return false;
+    }
+}
+
+
+impl fmt::Display for HidppMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:02x} {:02x} {:02x} fn {:x} swid {:x}]",
+            self.report_id, self.device_index, self.feature_index, self.function, self.swid)?;
+        for b in &self.params {
+            write!(f, " {:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_short_and_long_reports() {
+        let short = HidppMessage::short(0xff, 0x0f, 0x1, 0xa, &[0x01, 0x02]);
+        assert_eq!(short.encode().unwrap(), vec![0x10, 0xff, 0x0f, 0x1a, 0x01, 0x02, 0x00]);
+
+        let long = HidppMessage::long(0xff, 0x0f, 0x6, 0xa, &[0x00, 0x01]);
+        let bytes = long.encode().unwrap();
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(&bytes[..6], &[0x11, 0xff, 0x0f, 0x6a, 0x00, 0x01]);
+        assert!(bytes[6..].iter().all(|&b| b == 0x00));
+
+        let register = HidppMessage::register(0xff, GET_REGISTER, 0xf1, &[0x01]);
+        assert_eq!(register.encode().unwrap(), vec![0x10, 0xff, 0x81, 0xf1, 0x01, 0x00, 0x00]);
+
+        let long_register = HidppMessage::register(0xff, SET_LONG_REGISTER, 0x63, &[0x90, 0x01]);
+        assert_eq!(&long_register.encode().unwrap()[..6], &[0x11, 0xff, 0x82, 0x63, 0x90, 0x01]);
+    }
+
+    #[test]
+    fn rejects_what_does_not_fit() {
+        assert!(HidppMessage::short(0xff, 0x00, 0x10, 0x0, &[]).encode().is_err());
+        assert!(HidppMessage::short(0xff, 0x00, 0x0, 0x10, &[]).encode().is_err());
+        assert!(HidppMessage::short(0xff, 0x00, 0x0, 0x0, &[0; 4]).encode().is_err());
+        assert!(HidppMessage::long(0xff, 0x00, 0x0, 0x0, &[0; 17]).encode().is_err());
+    }
+
+    #[test]
+    fn parses_what_it_encodes() {
+        let message = HidppMessage::long(0xff, 0x0b, 0x5, 0xa, &[0x00, 0x01, 0x00, 0x10]);
+        let parsed = HidppMessage::parse(&message.encode().unwrap()).unwrap();
+        assert_eq!(parsed.report_id, REPORT_LONG);
+        assert_eq!(parsed.feature_index, 0x0b);
+        assert_eq!(parsed.function, 0x5);
+        assert_eq!(parsed.swid, 0xa);
+        assert_eq!(parsed.params.len(), 16);
+        assert_eq!(&parsed.params[..4], &[0x00, 0x01, 0x00, 0x10]);
+        assert_eq!(parsed.param(20), 0x00);
+    }
+
+    #[test]
+    fn rejects_bad_reports() {
+        assert!(HidppMessage::parse(&[]).is_err());
+        assert!(HidppMessage::parse(&[0x01, 0xff, 0x00, 0x00]).is_err());
+        assert!(HidppMessage::parse(&[0x11, 0xff, 0x00, 0x1a, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn decodes_error_replies() {
+        let hidpp20 = HidppMessage::parse(&[0x11, 0xff, 0xff, 0x0b, 0x6a, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert_eq!(hidpp20.error(), Some(HidppError::NotAllowed));
+
+        let hidpp10 = HidppMessage::parse(&[0x10, 0xff, 0x8f, 0x00, 0x1a, 0x01, 0x00]).unwrap();
+        assert_eq!(hidpp10.error(), Some(HidppError::InvalidSubId));
+
+        assert_eq!(HidppError::from_hidpp20(0x42), HidppError::Other(0x42));
+        assert_eq!(HidppMessage::short(0xff, 0x00, 0x1, 0xa, &[]).error(), None);
+    }
+
+    #[test]
+    fn matches_replies_to_requests() {
+        let request = HidppMessage::long(0xff, 0x0b, 0x6, 0xa, &[]);
+
+        assert!(HidppMessage::long(0xff, 0x0b, 0x6, 0xa, &[0x01]).answers(&request));
+        assert!(!HidppMessage::long(0xff, 0x0b, 0x6, 0xb, &[]).answers(&request));
+        assert!(!HidppMessage::long(0xff, 0x0b, 0x5, 0xa, &[]).answers(&request));
+        assert!(!HidppMessage::long(0x01, 0x0b, 0x6, 0xa, &[]).answers(&request));
+
+        // errors carry the request's feature index and function/swid one byte later
+        let error = HidppMessage::parse(&[0x11, 0xff, 0xff, 0x0b, 0x6a, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(error.answers(&request));
+        let other_error = HidppMessage::parse(&[0x11, 0xff, 0xff, 0x0c, 0x6a, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(!other_error.answers(&request));
+
+        let ping = HidppMessage::short(0xff, 0x00, 0x1, 0xa, &[]);
+        let hidpp10 = HidppMessage::parse(&[0x10, 0xff, 0x8f, 0x00, 0x1a, 0x01, 0x00]).unwrap();
+        assert!(hidpp10.answers(&ping));
+    }
+}
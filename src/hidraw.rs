@@ -0,0 +1,147 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use libc;
+use transport::Transport;
+
+/**
+ * Talks to the device through a linux /dev/hidrawN node
+ * The kernel driver stays bound, so the mouse keeps working as a pointer
+ */
+pub struct HidrawTransport {
+    file: File
+}
+
+
+impl HidrawTransport {
+
+    /**
+     * Searches sysfs for the hidraw node of the HID++ interface
+     * belonging to the given usb ids
+     */
+    pub fn find(vendor_id: u16, product_id: u16) -> io::Result<PathBuf> {
+        let hid_id = format!("HID_ID=0003:{:08X}:{:08X}", vendor_id, product_id);
+
+        let mut nodes: Vec<PathBuf> = Vec::new();
+        for entry in fs::read_dir("/sys/class/hidraw")? {
+            nodes.push(entry?.path());
+        }
+        nodes.sort();
+
+        for node in nodes {
+            let device = node.join("device");
+            let uevent = match fs::read_to_string(&device.join("uevent")) {
+                Ok(text) => text,
+                Err(_) => continue
+            };
+            if !uevent.lines().any(|line| line.eq_ignore_ascii_case(&hid_id)) {
+                continue;
+            }
+
+            // the mouse exposes several interfaces, only one carries HID++ reports
+            let descriptor = match fs::read(&device.join("report_descriptor")) {
+                Ok(bytes) => bytes,
+                Err(_) => continue
+            };
+            if has_report_id(&descriptor, 0x10) && has_report_id(&descriptor, 0x11) {
+                if let Some(name) = node.file_name() {
+                    return Ok(Path::new("/dev").join(name));
+                }
+            }
+        }
+
+        return Err(io::Error::new(io::ErrorKind::NotFound,
+            format!("no hidraw node for {:04x}:{:04x}", vendor_id, product_id)));
+    }
+
+    pub fn open(path: &Path) -> io::Result<HidrawTransport> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        return Ok(HidrawTransport {
+            file: file
+        });
+    }
+
+    /**
+     * Writes a whole output report, the first byte is the report id
+     */
+    fn write_report(&mut self, msg: &[u8]) -> io::Result<()> {
+        let written = self.file.write(msg)?;
+        if written != msg.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "short hidraw write"));
+        }
+        Ok(())
+    }
+}
+
+
+impl Transport for HidrawTransport {
+
+    fn send_short(&mut self, msg: &[u8; 7]) -> io::Result<()> {
+        self.write_report(msg)
+    }
+
+    fn send_long(&mut self, msg: &[u8; 20]) -> io::Result<()> {
+        self.write_report(msg)
+    }
+
+    fn read_report(&mut self, out: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let mut poll_fd = libc::pollfd {
+            fd: self.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0
+        };
+        let timeout_ms = (timeout.as_secs() * 1000) as i32 + (timeout.subsec_nanos() / 1000000) as i32;
+
+        // poll fails with EINTR on any signal, even with SA_RESTART,
+        // keep waiting so a started write is finished before the session stops
+        let ready = loop {
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+            if ready >= 0 {
+                break ready;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        };
+        if ready == 0 {
+            return Ok(0);
+        }
+        self.file.read(out)
+    }
+}
+
+
+/**
+ * Checks a hid report descriptor for a Report ID item with the given id
+ */
+fn has_report_id(descriptor: &[u8], report_id: u8) -> bool {
+    let mut i = 0;
+    while i < descriptor.len() {
+        let prefix = descriptor[i];
+
+        // long items carry their size in the next byte
+        if prefix == 0xfe {
+            if i + 1 >= descriptor.len() {
+                break;
+            }
+            i += 3 + descriptor[i + 1] as usize;
+            continue;
+        }
+
+        let size = match prefix & 0x03 {
+            3 => 4,
+            n => n as usize
+        };
+        if prefix & 0xfc == 0x84 && size == 1 && i + 1 < descriptor.len() && descriptor[i + 1] == report_id {
+            return true;
+        }
+        i += 1 + size;
+    }
+    return false;
+}
+
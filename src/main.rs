@@ -1,786 +1,34 @@
-extern crate rand;
 extern crate libusb;
-extern crate crc;
+extern crate libc;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 use std::env;
+use std::process;
 use std::str;
-use std::io::Read;
-use std::io::Seek;
-use std::io::SeekFrom;
-use std::fs;
-use std::fs::File;
-use std::time::Duration;
-use std::path::PathBuf;
-use std::u8;
-use rand::Rng;
-use crc::{crc16, Hasher16};
-
-/*
- * sets and reads fan and pump speeds
- */
-struct UsbController<'a> {
-    handle: libusb::DeviceHandle<'a>,
-    interface: u8,
-    read_address: u8,
-    write_address: u8,
-    print_messages: bool
-}
-
-
-impl<'a> UsbController<'a> {
-    fn open(device: &'a libusb::Device, print_messages: bool) -> UsbController<'a> {
-
-        let mut selected_interface = 0x00;
-        let mut selected_read_address = 0x82;
-        let mut selected_write_address = 0x00;
-
-        let config = device.active_config_descriptor().unwrap();
-        for interface in config.interfaces() {
-            selected_interface = interface.number();
-            for descriptor in interface.descriptors() {
-                for endpoint in descriptor.endpoint_descriptors() {
-                    if endpoint.direction() == libusb::Direction::In {
-                        selected_read_address = endpoint.address();
-                    }
-                    else {
-                        selected_write_address = endpoint.address();
-                    }
-                }
-            }
-        }
-
-        if print_messages {
-            println!("Opening interface 0x{:02x}", selected_interface);
-            println!("Read address 0x{:02x}", selected_read_address);
-            println!("Write address 0x{:02x}", selected_write_address);
-        }
-
-        return UsbController {
-            handle: device.open().unwrap(),
-            interface: selected_interface,
-            read_address: selected_read_address,
-            write_address: selected_write_address,
-            print_messages: print_messages
-        }
-    }
-
-    fn claim(&mut self) {
-        self.handle.detach_kernel_driver(self.interface);
-        self.handle.claim_interface(self.interface);
-    }
-
-    fn release(&mut self) {
-        self.handle.release_interface(self.interface);
-    }
-
-    fn read_response(&mut self, out: &mut [u8]) -> usize {
-        let result = self.handle.read_interrupt(self.read_address, out, Duration::from_secs(1)).unwrap_or(0);
-        if self.print_messages {
-            print!("read {:03} bytes: [", result);
-            for b in 0..result {
-                print!("{:x}, ", out[b]);
-            }
-            println!("]");
-        }
-        return result;
-    }
-
-
-    /**
-     * Writes 7 byte packets to 0x0210
-     */
-    fn send_ctl_packet_7(&mut self, msg: &[u8]) {
-        //println!("write ctl {:03} bytes", msg.len());
-        let result = self.handle.write_control(0x21, 9, 0x0210, 1, msg, Duration::from_secs(1)).unwrap();
-    }
-
-    /**
-     * Writes 20 byte packets to 0x0211
-     */
-    fn send_ctl_packet_20(&mut self, msg: &[u8]) {
-        //println!("write ctl {:03} bytes", msg.len());
-        let result = self.handle.write_control(0x21, 9, 0x0211, 1, msg, Duration::from_secs(1)).unwrap();
-    }
-
-    fn send_0_root(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) {
-        let byte_3 = 0x00 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, byte_3, offset, param_b, param_c
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    /**
-     * get feature data
-     * first param is an offset for large data
-     */
-    fn send_1_get_features(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) {
-        let byte_3 = 0x10 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, byte_3, offset, param_b, param_c
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    /**
-     * checks a feature is available?
-     * -- when param_b 0x00 it will read
-     * otherwise saves a key-value?
-     */
-    fn send_2_connected(&mut self, di: u8, fi: u8, swid: u8, offset: u8, param_b: u8, param_c: u8) {
-        let byte_3 = 0x20 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, byte_3, offset, param_b, param_c
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    /**
-     * Changes the Profile
-     * reports info when profile = 0x00
-     * changes led colors
-     */
-    fn send_3_profile(&mut self, di: u8, fi: u8, swid: u8, profile: u8) {
-        let byte_3 = 0x30 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, byte_3, 0x00, profile, 0x00
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        // returns many packets
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-        self.read_response(&mut resp);
-        self.read_response(&mut resp);
-    }
-
-
-    /**
-     * Param is unknown, usually set to 0x01
-     */
-    fn send_3_color(&mut self, di: u8, fi: u8, swid: u8, led_mode: u8, led_r: u8, led_g: u8, led_b: u8, cycle_a: u8, cycle_b: u8, cycle_c: u8, param: u8) {
-        let command_3 = 0x30 + swid;
-        let buf: [u8; 20] = [
-            0x11, di, fi, command_3, 0x00, led_mode, led_r, led_g,
-            led_b, param, 0x00, cycle_a, cycle_b, cycle_c, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_20(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    /**
-     * Returns an error code and current profile
-     */
-    fn send_4_status(&mut self, di: u8, fi: u8, swid: u8) -> (u8, u8) {
-        let byte_3 = 0x40 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, byte_3, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        // returns err code and current profile
-        let mut resp: [u8; 20] = [0; 20];
-        let len = self.read_response(&mut resp);
-
-        if len == 20 {
-            return (resp[4], resp[5]);
-        }
-        else {
-            return (0, 0);
-        }
-    }
-
-    /**
-     * Reads the record 10 bytes at a time, starting at offset
-     * a and profile = 1, 1 : 0, {1-5}
-     */
-    fn send_5_read_record(&mut self, di: u8, fi: u8, swid: u8, id_a: u8, profile: u8, offset: u8) {
-        let byte_3 = 0x50 + swid;
-        let buf: [u8; 20] = [
-            0x11, di, fi, byte_3, id_a, profile, 0x00, offset,
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_20(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    /**
-     * Profile is 0x01 for profile 1
-     * Profile is 0x00 when switching profile
-     */
-    fn send_6_start_record(&mut self, di: u8, fi: u8, swid: u8, profile: u8) {
-        let byte_3 = 0x60 + swid;
-        let buf20: [u8; 20] = [
-            0x11, di, fi, byte_3, 0x00, profile, 0x00, 0x00,
-            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_20(&buf20);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-
-    fn send_7_record(&mut self, di: u8, fi: u8, swid: u8, params: [u8; 16]) {
-        let command_7 = 0x70 + swid;
-        let buf: [u8; 20] = [
-            0x11, di, fi, command_7, params[0], params[1], params[2], params[3],
-            params[4], params[5], params[6], params[7], params[8], params[9], params[10], params[11],
-            params[12], params[13], params[14], params[15],
-        ];
-        self.send_ctl_packet_20(&buf);
-
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-    }
-
-    fn send_8_end_record(&mut self, di: u8, fi: u8, swid: u8) {
-
-        // the end of the command type 7 sequence
-        // seems to get 3 response lines
-        let command_8 = 0x80 + swid;
-        let buf: [u8; 7] = [
-            0x10, di, fi, command_8, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_7(&buf);
-
-        // extra output after command 8
-        // a previous command triggers the extra outputs
-        let mut resp: [u8; 20] = [0; 20];
-        self.read_response(&mut resp);
-        self.read_response(&mut resp);
-        self.read_response(&mut resp);
-    }
-
-
-    fn get_magic_numbers(&self) -> (u8, u8) {
-        // seems to link to the last 2 bytes somehow
-        // affected by the profile but not the content
-        //
-        // 1a -> 31 fe
-        // 1b -> d8 ae
-        // 1c -> 8e e5
-        // 1d -> 0f ce
-        // 2f -> e1 b6
-        // 30 -> 4c 75
-        // 33 -> 70 86
-        // 35 -> e5 73
-        // 2f -> e1 b6
-        // 37 -> 96 20
-
-        // 500hz
-        // 30 -> 4c 75
-        // 31 -> 03 d5
-        // 32 -> 3f 26
-        // 34 -> aa d3
-        // 36 -> d9 80
-
-        // reset profile 1:
-        // 1d -> cd c4
-        // 1e -> 0f 2e
-
-        // profile 2:
-        // 0a -> cc a0
-        // 0c -> dd 58
-        // 0d -> 6c e1
-        // 0f -> 2f 20
-        // 10 -> 4c 68
-        // 11 -> cd 43
-        // 12 -> 3f 3b
-        // 13 -> be 10
-        // 14 -> 9a 5c
-        // 15 -> 1b 77
-        // 16 -> e9 0f
-        // 17 -> 68 24
-        // 18 -> a1 97
-        // 19 -> 20 bc
-        // 1a -> d2 c4
-        // 1b -> 53 ef
-
-        let cmd_param = 0x1d;
-        let checksum_a = 0xcd;
-        let checksum_b = 0xc4;
-
-        // Changing profile:
-        // Profile 1: e5 da
-        // profile 3: 00 -> 3c e2
-
-        return (checksum_a, checksum_b);
-    }
-
-
-    /**
-     * A big set of data send over multiple packets
-     */
-    fn send_group_7_record(&mut self, di: u8, fi: u8, swid: u8, write_id: u8, poll_rate: u16, dpi_array: &[u16]) {
-
-        // 0x00 = change profile
-        // 0x01 = 1000hz
-        // 0x02 = 500hz
-        let mut use_poll_rate = 0x01;
-        if (poll_rate == 500) {
-            use_poll_rate = 0x02;
-        }
-        else if (poll_rate == 333) {
-            use_poll_rate = 0x03;
-        }
-        else if (poll_rate == 250) {
-            use_poll_rate = 0x04;
-        }
-
-        // this must be incremented by 1 on each write
-        let cmd_param = write_id;
-        let checksum_a = 0xff;
-        let checksum_b = 0xff;
-
-        // send a 256 byte set of data
-        // Line 0 is the dpi settings
-        // should be ordered low to high
-        // 1st: 0x0190
-        // 2st: 0x0320
-
-        let mut dpi_1_hi = 0x01;
-        let mut dpi_1_lo = 0x90;
-        let mut dpi_2_hi = 0x02;
-        let mut dpi_2_lo = 0x8a;
-        let mut dpi_3_hi = 0x03;
-        let mut dpi_3_lo = 0x84;
-        let mut dpi_4_hi = 0x04;
-        let mut dpi_4_lo = 0x7e;
-        let mut dpi_5_hi = 0x00; // 0 to disable
-        let mut dpi_5_lo = 0x00;
-
-        if dpi_array.len() > 0 {
-            dpi_1_hi = (dpi_array[0] >> 8) as u8;
-            dpi_1_lo = (dpi_array[0] & 0xff) as u8;
-        }
-        if dpi_array.len() > 1 {
-            dpi_2_hi = (dpi_array[1] >> 8) as u8;
-            dpi_2_lo = (dpi_array[1] & 0xff) as u8;
-        }
-        if dpi_array.len() > 2 {
-            dpi_3_hi = (dpi_array[2] >> 8) as u8;
-            dpi_3_lo = (dpi_array[2] & 0xff) as u8;
-        }
-        if dpi_array.len() > 3 {
-            dpi_4_hi = (dpi_array[3] >> 8) as u8;
-            dpi_4_lo = (dpi_array[3] & 0xff) as u8;
-        }
-        if dpi_array.len() > 4 {
-            dpi_5_hi = (dpi_array[4] >> 8) as u8;
-            dpi_5_lo = (dpi_array[4] & 0xff) as u8;
-        }
-
-        let mut packets: [[u8; 16]; 16] = [
-            [use_poll_rate, 0x01, 0x00, dpi_1_lo, dpi_1_hi, dpi_2_lo, dpi_2_hi, dpi_3_lo, dpi_3_hi, dpi_4_lo, dpi_4_hi, dpi_5_lo, dpi_5_hi, 0xff, 0xff, 0xff],
-            [0xff, 0x00, cmd_param, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
-            [0x80, 0x01, 0x00, 0x10, 0x90, 0x05, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
-            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
-            [0x80, 0x01, 0x00, 0x10, 0x90, 0x05, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            [0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00, 0x00],
-            [0x50, 0x00, 0x72, 0x00, 0x6f, 0x00, 0x66, 0x00, 0x69, 0x00, 0x6c, 0x00, 0x65, 0x00, 0x20, 0x00], // "Profile"
-            [0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "1" -- Profile 1 is saved as the profile name
-            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-            [0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00, 0x00],
-            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, checksum_a, checksum_b], // sometimes has 0x00 in byte 4-6
-        ];
-
-        // run each byte in order into the crc function
-        // but skip the last 2 bytes from the last line
-        let mut crc_value = 0xffff;
-        let mut crc_count = 0;
-        for &packet in &packets {
-            let mut range = packet.len();
-            if crc_count == 0xf0 {
-                range = packet.len() - 2; // skip last 2 bytes
-            }
-            for x in 0..range {
-                crc_value = UsbController::crc_update(crc_value, packet[x]);
-                crc_count += 1;
-            }
-        }
-
-        let crc_lo = (crc_value & 0xff) as u8;
-        let crc_hi = (crc_value >> 8) as u8;
-        println!("CRC {:02x}:{:02x}", crc_hi, crc_lo);
-
-        // cd, c4 is the correct crc.
-        // how is it calculated?
-        packets[15][14] = crc_hi;
-        packets[15][15] = crc_lo;
-
-        for &packet in &packets {
-            self.send_7_record(di, fi, swid, packet);
-        }
-    }
-
-    /**
-     * Copy of devices crc function
-     */
-    fn crc_update(mut crc: u16, data: u8) -> u16 {
-      crc ^= ((data as u16) << 8);
-      for x in 0..8 {
-          if (crc & 0x8000) == 0x8000 {
-              crc = ((crc << 1) ^ 0x1021) & 0xFFFF;
-          }
-          else {
-             crc <<= 1;
-          }
-      }
-      crc &= 0xFFFF;
-      return crc;
-  }
-
-
-    fn send_group_7_enable_profile(&mut self, di: u8, fi: u8, swid: u8) {
-        println!("Enable Profile");
-
-        let checksum_a = 0xe5;
-        let checksum_b = 0xda;
-
-        // Changing profile:
-        // Profile 1: e5 da
-        // profile 3: 00 -> 3c e2
-
-        // profile on/off switches
-        let p1 = 0x01;
-        let p2 = 0x00;
-        let p3 = 0x00;
-        let p4 = 0x00;
-        let p5 = 0x00;
-
-        let packets: [[u8; 16]; 16] = [
-            [0x00, 0x01, p1, 0x00, 0x00, 0x02, p2, 0x00, 0x00, 0x03, p3, 0x00, 0x00, 0x04, p4, 0x00],
-            [0x00, 0x05, p5, 0x00, 0xff, 0xff, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
-            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, checksum_a, checksum_b],
-        ];
-
-        for &packet in &packets {
-            self.send_7_record(di, fi, swid, packet);
-        }
-    }
-
-    /*
-     * Runs when device is first connected
-     */
-    fn write_init_commands(&mut self) {
-
-        let device_index = 0xff;
-        let feature_index = 0x0f; // 0x0f is dpi, 0x0e is leds
-        let swid = 0xa;
-
-        let command_type_0 = 0x0c; // root
-        let command_type_1 = 0x1c; //
-        let command_type_2 = 0x2c; // check connected
-        let command_type_3 = 0x3c; // color setting
-        let command_type_4 = 0x4c; // get status, returns err and current profile
-        let command_type_6 = 0x6c;
-        let command_type_7 = 0x7c;
-        let command_type_8 = 0x8c;
-        let command_type_b = 0xbc; // error handling?
-        let command_type_c = 0xcc; // error handling?
-
-        // reset logic when device is first connected
-        self.send_1_get_features(device_index, 0x00, swid, 0x00, 0x03, 0x00);
-        self.send_1_get_features(device_index, 0x00, swid, 0x00, 0x03, 0x39);
-
-        self.send_0_root(device_index, 0x00, swid, 0x00, 0x03, 0x00);
-        self.send_0_root(device_index, 0x02, swid, 0x00, 0x00, 0x00);
-
-        self.send_1_get_features(device_index, 0x02, swid, 0x00, 0x00, 0x00);
-
-        self.send_0_root(device_index, 0x00, swid, 0x00, 0x05, 0x00);
-
-        self.send_2_connected(device_index, 0x03, swid, 0x00, 0x00, 0x00);
-
-        self.send_0_root(device_index, 0x02, swid, 0x00, 0x00, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x10, 0x01, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x1f, 0x20, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x10, 0x00, 0x00);
-
-        self.send_1_get_features(device_index, 0x02, swid, 0x00, 0x00, 0x00);
-
-        self.send_0_root(device_index, 0x03, swid, 0x00, 0x00, 0x00);
-
-        // This returns response of device name string over 2 responses
-        // 0x10 is an offset
-        self.send_1_get_features(device_index, 0x03, swid, 0x00, 0x00, 0x00);
-        self.send_1_get_features(device_index, 0x03, swid, 0x10, 0x00, 0x00);
-
-        self.send_0_root(device_index, 0x00, swid, 0x81, 0x10, 0x00);
-        self.send_0_root(device_index, 0x10, swid, 0x00, 0x00, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x80, 0x90, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x13, 0x00, 0x00);
-        self.send_0_root(device_index, 0x00, swid, 0x81, 0x00, 0x00);
-
-        self.send_2_connected(device_index, 0x0f, swid, 0x00, 0x00, 0x00);
-
-        self.send_0_root(device_index, 0x00, swid, 0x80, 0x70, 0x00);
-        self.send_0_root(device_index, 0x0e, swid, 0x00, 0x00, 0x00);
-
-        self.send_1_get_features(device_index, 0x0e, swid, 0x00, 0x00, 0x00);
-        self.send_2_connected(device_index, 0x0e, swid, 0x00, 0x00, 0x00);
 
-        // adds 0x01, 0x01 params
-        let reset_w: [u8; 7] = [
-            0x10, 0xff, 0x0e, command_type_8, 0x01, 0x01, 0x00
-        ];
-        self.send_ctl_7(&reset_w);
-
-        self.send_4_status(device_index, feature_index, swid);
-
-        // set profile
-        self.switch_to_profile(0x01);
-
-        // these 7 byte packets seem to reset state
-        // used on setting dpi
-        let buf7_8: [u8; 7] = [
-            0x10, 0xff, 0x0f, command_type_b, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_7(&buf7_8);
-
-        // used on setting dpi
-        let buf7_7: [u8; 7] = [
-            0x10, 0xff, 0x0f, command_type_c, 0x03, 0x00, 0x00
-        ];
-        self.send_ctl_7(&buf7_7);
-    }
-
-    /**
-     * Writes 7 byte packets to 0x0210
-     * Use send_ctl_packet_7 instead
-     */
-    fn send_ctl_7(&mut self, msg: &[u8]) {
-        println!("write ctl {:03} bytes", msg.len());
-        let result = self.handle.write_control(0x21, 9, 0x0210, 1, msg, Duration::from_secs(10)).unwrap();
-        self.print_status();
-    }
-
-    /**
-     * Writes 20 byte packets to 0x0211
-     * Use send_ctl_packet_20 instead
-     */
-    fn send_ctl_20(&mut self, msg: &[u8]) {
-        println!("write ctl {:03} bytes", msg.len());
-        let result = self.handle.write_control(0x21, 9, 0x0211, 1, msg, Duration::from_secs(10)).unwrap();
-        self.print_status();
-    }
-
-
-    fn send_msg(&mut self, msg: &[u8]) {
-        let result = self.handle.write_interrupt(self.write_address, msg, Duration::from_secs(10)).unwrap();
-        self.print_status();
-    }
-
-
-    /**
-     * Make sure no messages are queued to be read
-     */
-    fn clear_reads(&mut self) {
-        let mut state = 1;
-        let mut resp: [u8; 20] = [0; 20];
-        while state > 0 {
-            state = self.read_response(&mut resp);
-        }
-    }
-
-
-    /**
-     * Normally begins with 0x11, 0xff, 0x0f, 0x7c ...
-     */
-    fn print_status(&mut self) {
-        let device_index = 0xff;
-        let feature_index = 0x0f;
-        let swid = 0xa;
-
-        let (err, profile) = self.send_4_status(device_index, feature_index, swid);
-
-        println!("Error code: {:02x}, Profile {:02x}", err, profile);
-    }
-
-    /**
-     * 0x01 to 0x05
-     */
-    fn switch_to_profile(&mut self, n: u8) {
-        let device_index = 0xff;
-        let feature_index = 0x0f;
-        let swid = 0xa;
-
-        self.send_4_status(device_index, feature_index, swid);
-
-        self.send_3_profile(device_index, feature_index, swid, n);
-
-        self.send_4_status(device_index, feature_index, swid);
-    }
-
-    fn enable_profile(&mut self, n: u8) {
-        let device_index = 0xff;
-        let feature_index = 0x0f; // 0x0f is dpi, 0x0e is leds
-        let swid = 0xa;
-
-        println!("Enabling profile");
-        self.send_6_start_record(device_index, feature_index, swid, 0x00);
-
-        self.send_group_7_enable_profile(device_index, feature_index, swid);
-
-        // the end of the command type 7 sequence
-        // seems to get 3 response lines
-
-        let command_8 = 0x80 + swid;
-        let command_8_end: [u8; 7] = [
-            0x10, device_index, feature_index, command_8, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_packet_7(&command_8_end);
-    }
-
-
-    /**
-     * Mode: 0x00 = off, 0x01 = static, 0x02 = cycle
-     */
-    fn apply_color(&mut self, mode: &LedMode) {
-        let device_index = 0xff;
-        let feature_index = 0x0f; // 0x0f is dpi, 0x0e is leds
-        let swid = 0xa;
-
-        self.send_4_status(device_index, feature_index, swid);
-
-        // only for the LED control
-        self.send_0_root(device_index, 0x0e, swid, 0x00, 0x00, 0x00);
-
-        let mut mode_id = 0x00;
-
-        // static mode params
-        let mut led_r = 0x00;
-        let mut led_g = 0x00;
-        let mut led_b = 0x00;
-
-        // cycle mode params (are 0x00 for other modes)
-        let mut cycle_a = 0x2a; // speed major
-        let mut cycle_b = 0xf8; // speed minor
-        let mut cycle_c = 0x64; // brightness
-        let mut unknown = 0x01;
-
-        match mode {
-            LedMode::Off => {
-                mode_id = 0x00;
-            }
-            LedMode::Static {r, g, b} => {
-                mode_id = 0x01;
-                led_r = *r;
-                led_g = *g;
-                led_b = *b;
-            },
-            LedMode::Cycle {speed, brightness} => {
-                mode_id = 0x02;
-                cycle_a = (*speed >> 8) as u8;
-                cycle_b = (*speed & 0xff) as u8;
-                cycle_c = *brightness;
-            }
-        }
-
-        self.send_3_color(device_index, 0x0e, swid, mode_id, led_r, led_g, led_b, cycle_a, cycle_b, cycle_c, unknown);
-    }
-
-    /**
-     * Not used, but keeping example of reading existing settings
-     */
-    fn before_apply(&mut self, profile: u8) {
-        let device_index = 0xff;
-        let feature_index = 0x0f; // 0x0f is dpi, 0x0e is leds
-        let swid = 0xa;
-
-        println!("Existing settings:");
-        self.send_5_read_record(device_index, feature_index, swid, 0x00, profile, 0x10);
-        self.send_5_read_record(device_index, feature_index, swid, 0x00, profile, 0xe0);
-
-        self.switch_to_profile(profile);
-
-        // try to fix err 0x01
-        self.send_0_root(device_index, 0x00, swid, 0x80, 0x60, 0x00);
-        self.send_0_root(device_index, 0x0d, swid, 0x00, 0x00, 0x00);
-
-        // these 7 byte packets seem to reset state
-        // used on setting dpi
-        let command_b = 0xb0 + swid;
-        let buf7_8: [u8; 7] = [
-            0x10, 0xff, 0x0f, command_b, 0x00, 0x00, 0x00
-        ];
-        self.send_ctl_7(&buf7_8);
-
-        // used on setting dpi
-        let command_c = 0xc0 + swid;
-        let buf7_9: [u8; 7] = [
-            0x10, 0xff, 0x0f, command_c, 0x03, 0x00, 0x00
-        ];
-        self.send_ctl_7(&buf7_9);
-    }
-
-    fn apply_settings(&mut self, profile: u8, poll_rate: u16, dpi_array: &[u16]) {
-        let device_index = 0xff;
-        let feature_index = 0x0f; // 0x0f is dpi, 0x0e is leds
-        let swid = 0xa;
-        let mut use_profile = profile;
-
-        // is a profile isn't given find the current profile
-        while use_profile == 0 {
-            let (err, p) = self.send_4_status(device_index, feature_index, swid);
-            use_profile = p;
-        }
-
-        println!("Setting profile {:02x}", use_profile);
-
-        // this first attempt fails from non-incrementing id (0x1c) but alters the current id
-        // allows the check to be bypassed on the retry
-        self.send_6_start_record(device_index, feature_index, swid, use_profile);
-        self.send_group_7_record(device_index, feature_index, swid, 0x1c, poll_rate, dpi_array);
-        self.send_8_end_record(device_index, feature_index, swid);
-
-        // try again with incremented id 0x1c -> 0x1d
-        // the beginning of actually altering the settings
-        self.send_6_start_record(device_index, feature_index, swid, use_profile);
-        self.send_group_7_record(device_index, feature_index, swid, 0x1d, poll_rate, dpi_array);
-
-        // ends the record and applies settings
-        self.send_8_end_record(device_index, feature_index, swid);
-
-        // get final status
-        self.send_4_status(device_index, feature_index, swid);
-    }
-}
+mod transport;
+mod hidpp;
+mod features;
+mod device_info;
+mod checksum;
+mod profile;
+mod dpi;
+mod backup;
+mod error;
+mod controller;
+mod hidraw;
+mod session;
+mod commands;
+
+use transport::{Transport, LibusbTransport};
+use controller::UsbController;
+use error::LogictlError;
+use hidraw::HidrawTransport;
+use session::Session;
+use profile::{OnboardMode, ProfileSettings};
 
 
 fn print_endpoint(endpoint: libusb::EndpointDescriptor) {
@@ -794,15 +42,15 @@ fn print_endpoint(endpoint: libusb::EndpointDescriptor) {
 }
 
 
-fn print_device(device: &libusb::Device) {
-    let device_desc = device.device_descriptor().unwrap();
+fn print_device(device: &libusb::Device) -> Result<(), LogictlError> {
+    let device_desc = device.device_descriptor()?;
     println!("Bus {:03} Device {:03} ID {:04x}:{:04x}",
         device.bus_number(),
         device.address(),
         device_desc.vendor_id(),
         device_desc.product_id());
 
-    let config = device.active_config_descriptor().unwrap();
+    let config = device.active_config_descriptor()?;
     println!("Number {}, Interfaces {}", config.number(), config.num_interfaces());
 
     for interface in config.interfaces() {
@@ -814,6 +62,30 @@ fn print_device(device: &libusb::Device) {
             }
         }
     }
+
+    Ok(())
+}
+
+/**
+ * Commands given as the first argument
+ */
+enum Command {
+    Features,
+    Info,
+    ProfileDump,
+    ProfileRestore,
+    ProfileList,
+    ProfileEnable(Vec<u8>),
+    ProfileDisable(Vec<u8>),
+    ProfileSlot { slot: u8, sector: u16 },
+    Backup,
+    RestoreAll,
+    ModeGet,
+    ModeSet(OnboardMode),
+    DpiGet,
+    DpiSet(u16),
+    RateGet,
+    RateSet(u16)
 }
 
 enum LedMode {
@@ -824,13 +96,19 @@ struct Config {
     vendor_id: u16,
     product_id: u16,
     print_endpoints: bool,
+    use_hidraw: bool,
     print_status: bool,
     clear_queue: bool,
+    command: Option<Command>,
+    json: bool,
+    profile: u8,
+    input: Option<String>,
+    output: Option<String>,
+    json_output: Option<String>,
     switch_to_profile: u8,
-    write_profile: bool,
+    profile_settings: ProfileSettings,
     write_color: bool,
-    poll_rate: u16,
-    dpi_array: [u16; 5],
+    dpi_stage: u8,
     led_mode: LedMode,
 }
 
@@ -841,13 +119,19 @@ impl Config {
             vendor_id: 0x046d,
             product_id: 0xc08c,
             print_endpoints: false,
+            use_hidraw: false,
             print_status: false,
             clear_queue: true,
+            command: None,
+            json: false,
+            profile: 0x00,
+            input: None,
+            output: None,
+            json_output: None,
             switch_to_profile: 0x00,
-            write_profile: false,
+            profile_settings: ProfileSettings::new(),
             write_color: false,
-            poll_rate: 1000,
-            dpi_array: [400, 800, 1600, 3200, 0],
+            dpi_stage: 0,
             led_mode: LedMode::Static{ r: 0xff, g: 0xff, b: 0x00 }
         }
     }
@@ -857,81 +141,464 @@ impl Config {
 /**
  * Set selected device to some mode.
  */
-fn select_device(device: libusb::Device, config: &Config) {
-    let mut controller = UsbController::open(&device, config.print_endpoints);
+fn select_device(device: libusb::Device, config: &Config) -> Result<(), LogictlError> {
+    let transport = LibusbTransport::open(&device, config.print_endpoints)?;
+    let controller = UsbController::new(transport, config.print_endpoints);
 
     // print all device endpoint information
     if (config.print_endpoints) {
-        print_device(&device);
+        print_device(&device)?;
+    }
+
+    run_commands(controller, config)
+}
+
+
+/**
+ * Runs the configured commands over any transport
+ * The session releases the device on every exit path
+ */
+fn run_commands<T: Transport>(controller: UsbController<T>, config: &Config) -> Result<(), LogictlError> {
+    let mut session = Session::open(controller)?;
+
+    let result = execute_commands(&mut session, config);
+
+    if session::interrupted() {
+        eprintln!("Interrupted, releasing device");
+    }
+
+    // a failed command is more interesting than a failed release
+    let closed = session.close();
+    result?;
+    closed?;
+
+    if session::interrupted() {
+        return Err(LogictlError::Interrupted);
     }
+    Ok(())
+}
+
 
-    controller.claim();
+/**
+ * Each command in order, stopping at the first failure
+ */
+fn execute_commands<T: Transport>(controller: &mut UsbController<T>, config: &Config) -> Result<(), LogictlError> {
 
     // remove any queued usb responses
     if config.clear_queue {
-        controller.clear_reads();
+        controller.clear_reads()?;
+    }
+
+    // HID++ 1.0 devices only take register access, feature commands report them as unsupported
+    let protocol = controller.protocol_version()?;
+    if config.print_endpoints {
+        println!("HID++ {}", protocol);
+    }
+
+    match config.command {
+        Some(Command::Features) => commands::print_features(controller, config.json)?,
+        Some(Command::Info) => commands::print_info(controller, config.vendor_id, config.product_id, config.json)?,
+        Some(Command::ProfileDump) => {
+            if let Some(ref output) = config.output {
+                commands::dump_profile(controller, config.profile, output, config.json_output.as_ref().map(|p| p.as_str()))?;
+            }
+        },
+        Some(Command::ProfileRestore) => {
+            if let Some(ref input) = config.input {
+                commands::restore_profile(controller, config.profile, input)?;
+            }
+        },
+        Some(Command::ProfileList) => commands::print_directory(controller, config.json)?,
+        Some(Command::ProfileEnable(ref slots)) => commands::set_profiles_enabled(controller, slots, true)?,
+        Some(Command::ProfileDisable(ref slots)) => commands::set_profiles_enabled(controller, slots, false)?,
+        Some(Command::ProfileSlot { slot, sector }) => commands::set_profile_sector(controller, slot, sector)?,
+        Some(Command::ModeGet) => commands::print_mode(controller)?,
+        Some(Command::ModeSet(mode)) => commands::set_mode(controller, mode)?,
+        Some(Command::DpiGet) => commands::print_dpi(controller)?,
+        Some(Command::DpiSet(dpi)) => commands::set_dpi(controller, dpi)?,
+        Some(Command::RateGet) => commands::print_rate(controller)?,
+        Some(Command::RateSet(hz)) => commands::set_rate(controller, hz)?,
+        Some(Command::Backup) => {
+            if let Some(ref output) = config.output {
+                commands::backup(controller, config.vendor_id, config.product_id, output)?;
+            }
+        },
+        Some(Command::RestoreAll) => {
+            if let Some(ref input) = config.input {
+                commands::restore_all(controller, config.vendor_id, config.product_id, input)?;
+            }
+        },
+        None => {}
     }
 
     // change the current profile
-    if config.switch_to_profile > 0 {
-        controller.switch_to_profile(config.switch_to_profile);
+    if config.switch_to_profile > 0 && !session::interrupted() {
+        controller.switch_to_profile(config.switch_to_profile)?;
+    }
+
+    if !config.profile_settings.is_empty() && !session::interrupted() {
+        let target = if config.profile > 0 { config.profile } else { config.switch_to_profile };
+        controller.apply_settings(target, &config.profile_settings)?;
     }
 
-    if config.write_profile {
-        controller.apply_settings(config.switch_to_profile, config.poll_rate, &config.dpi_array);
+    if config.dpi_stage > 0 && !session::interrupted() {
+        controller.set_dpi_stage(config.dpi_stage)?;
     }
 
-    if config.write_color {
-        controller.apply_color(&config.led_mode);
+    if config.write_color && !session::interrupted() {
+        controller.apply_color(&config.led_mode)?;
     }
 
-    if config.print_status {
-        controller.print_status();
+    if config.print_status && !session::interrupted() {
+        controller.print_status()?;
     }
-    controller.release();
+
+    Ok(())
 }
 
 
-fn main() {
-    let mut config = Config::default();
+/**
+ * Takes the value following a flag
+ */
+fn next_value<'a, I: Iterator<Item = &'a String>>(args: &mut I, flag: &str) -> Result<&'a String, LogictlError> {
+    match args.next() {
+        Some(value) => Ok(value),
+        None => Err(LogictlError::Parse(format!("{} expects a value", flag)))
+    }
+}
+
+
+/**
+ * Parses r,g,b with each part 0-255
+ */
+fn parse_color(value: &str) -> Result<LedMode, LogictlError> {
+    let parts: Vec<&str> = value.split(',').collect();
+    if parts.len() != 3 {
+        return Err(LogictlError::Parse(format!("--color expects r,g,b, got '{}'", value)));
+    }
 
-    let mut read_color = false;
-    let mut read_profile = false;
-    let args: Vec<String> = env::args().collect();
-    for arg in args {
-
-        if read_color {
-            let parts: Vec<&str> = arg.split(',').collect();
-            if (parts.len() == 3) {
-                let r = parts[0].parse::<u8>().unwrap();
-                let g = parts[1].parse::<u8>().unwrap();
-                let b = parts[2].parse::<u8>().unwrap();
-                config.write_color = true;
-                config.led_mode = LedMode::Static{ r: r, g: g, b: b };
+    let mut rgb = [0u8; 3];
+    for i in 0..3 {
+        rgb[i] = match parts[i].trim().parse::<u8>() {
+            Ok(v) => v,
+            Err(_) => return Err(LogictlError::Parse(
+                format!("invalid color component '{}', expected 0-255", parts[i])))
+        };
+    }
+    return Ok(LedMode::Static{ r: rgb[0], g: rgb[1], b: rgb[2] });
+}
+
+
+/**
+ * Parses a profile number 1-5
+ */
+fn parse_profile(value: &str) -> Result<u8, LogictlError> {
+    let profile = match value.parse::<u8>() {
+        Ok(p) => p,
+        Err(_) => return Err(LogictlError::Parse(format!("invalid profile '{}'", value)))
+    };
+    if profile < 1 || profile > 5 {
+        return Err(LogictlError::Validation(format!("profile must be 1-5, got {}", profile)));
+    }
+    return Ok(profile);
+}
+
+
+/**
+ * Parses a dpi stage 1-5
+ */
+fn parse_stage(value: &str) -> Result<u8, LogictlError> {
+    let stage = match value.parse::<u8>() {
+        Ok(stage) => stage,
+        Err(_) => return Err(LogictlError::Parse(format!("invalid dpi stage '{}'", value)))
+    };
+    if stage < 1 || stage as usize > profile::DPI_COUNT {
+        return Err(LogictlError::Validation(format!("dpi stage must be 1-{}, got {}", profile::DPI_COUNT, stage)));
+    }
+    return Ok(stage);
+}
+
+
+/**
+ * Parses 1-5 comma separated dpi values, e.g. 400,800,1600
+ */
+fn parse_dpi_list(value: &str) -> Result<Vec<u16>, LogictlError> {
+    let mut dpi = Vec::new();
+    for part in value.split(',') {
+        match part.trim().parse::<u16>() {
+            Ok(0) => return Err(LogictlError::Validation("dpi stages cannot be 0".to_string())),
+            Ok(v) => dpi.push(v),
+            Err(_) => return Err(LogictlError::Parse(format!("invalid dpi '{}'", part)))
+        }
+    }
+    if dpi.len() > profile::DPI_COUNT {
+        return Err(LogictlError::Validation(format!("--dpi takes at most {} stages, got {}", profile::DPI_COUNT, dpi.len())));
+    }
+    return Ok(dpi);
+}
+
+
+/**
+ * Parses a comma separated list of profiles, e.g. 1,3,4
+ */
+fn parse_profile_list(value: &str) -> Result<Vec<u8>, LogictlError> {
+    let mut profiles = Vec::new();
+    for part in value.split(',') {
+        profiles.push(parse_profile(part.trim())?);
+    }
+    return Ok(profiles);
+}
+
+
+/**
+ * Parses a sector number, in hex when it starts with 0x
+ */
+fn parse_sector(value: &str) -> Result<u16, LogictlError> {
+    let parsed = if value.starts_with("0x") {
+        u16::from_str_radix(&value[2..], 16)
+    }
+    else {
+        value.parse::<u16>()
+    };
+    match parsed {
+        Ok(sector) => Ok(sector),
+        Err(_) => Err(LogictlError::Parse(format!("invalid sector '{}'", value)))
+    }
+}
+
+
+/**
+ * Commands with subcommands take the next argument as well
+ */
+fn parse_command<'a, I: Iterator<Item = &'a String>>(name: &str, args: &mut I) -> Result<Command, LogictlError> {
+    match name {
+        "features" => Ok(Command::Features),
+        "info" => Ok(Command::Info),
+        "backup" => Ok(Command::Backup),
+        "restore-all" => Ok(Command::RestoreAll),
+        "dpi" => {
+            let action = next_value(args, name)?;
+            match action.as_str() {
+                "get" => Ok(Command::DpiGet),
+                "set" => {
+                    let value = next_value(args, "dpi set")?;
+                    match value.parse::<u16>() {
+                        Ok(dpi) => Ok(Command::DpiSet(dpi)),
+                        Err(_) => Err(LogictlError::Parse(format!("invalid dpi '{}'", value)))
+                    }
+                },
+                _ => Err(LogictlError::Parse(format!("unknown dpi command '{}', expected get or set", action)))
+            }
+        },
+        "rate" => {
+            let action = next_value(args, name)?;
+            match action.as_str() {
+                "get" => Ok(Command::RateGet),
+                "set" => {
+                    let value = next_value(args, "rate set")?;
+                    match value.parse::<u16>() {
+                        Ok(hz) => Ok(Command::RateSet(hz)),
+                        Err(_) => Err(LogictlError::Parse(format!("invalid report rate '{}'", value)))
+                    }
+                },
+                _ => Err(LogictlError::Parse(format!("unknown rate command '{}', expected get or set", action)))
             }
-            read_color = false;
+        },
+        "mode" => {
+            let action = next_value(args, name)?;
+            match action.as_str() {
+                "get" => Ok(Command::ModeGet),
+                "set" => {
+                    let value = next_value(args, "mode set")?;
+                    match value.as_str() {
+                        "onboard" => Ok(Command::ModeSet(OnboardMode::Onboard)),
+                        "host" => Ok(Command::ModeSet(OnboardMode::Host)),
+                        _ => Err(LogictlError::Parse(format!("unknown mode '{}', expected onboard or host", value)))
+                    }
+                },
+                _ => Err(LogictlError::Parse(format!("unknown mode command '{}', expected get or set", action)))
+            }
+        },
+        "profile" => {
+            let action = next_value(args, name)?;
+            match action.as_str() {
+                "dump" => Ok(Command::ProfileDump),
+                "restore" => Ok(Command::ProfileRestore),
+                "list" => Ok(Command::ProfileList),
+                "enable" => Ok(Command::ProfileEnable(parse_profile_list(next_value(args, "profile enable")?)?)),
+                "disable" => Ok(Command::ProfileDisable(parse_profile_list(next_value(args, "profile disable")?)?)),
+                "slot" => {
+                    let slot = parse_profile(next_value(args, "profile slot")?)?;
+                    let sector = parse_sector(next_value(args, "profile slot")?)?;
+                    Ok(Command::ProfileSlot { slot: slot, sector: sector })
+                },
+                _ => Err(LogictlError::Parse(format!("unknown profile command '{}', expected dump, restore, list, enable, disable or slot", action)))
+            }
+        },
+        _ => Err(LogictlError::Parse(format!("unknown command '{}'", name)))
+    }
+}
+
+
+/**
+ * Commands that read a file named after them
+ */
+fn takes_input(command: &Option<Command>) -> bool {
+    match *command {
+        Some(Command::ProfileRestore) => true,
+        Some(Command::RestoreAll) => true,
+        _ => false
+    }
+}
+
+
+fn parse_args(args: &[String]) -> Result<Config, LogictlError> {
+    let mut config = Config::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--color" {
+            let value = next_value(&mut iter, arg)?;
+            config.led_mode = parse_color(value)?;
+            config.write_color = true;
+        }
+        else if arg == "--switch-profile" {
+            let value = next_value(&mut iter, arg)?;
+            config.switch_to_profile = parse_profile(value)?;
         }
-        else if read_profile {
-            config.switch_to_profile = arg.parse::<u8>().unwrap();
-            read_profile = false;
+        else if arg == "--profile" {
+            let value = next_value(&mut iter, arg)?;
+            config.profile = parse_profile(value)?;
         }
-        else if arg == "--color" {
-            read_color = true;
+        else if arg == "-o" || arg == "--output" {
+            config.output = Some(next_value(&mut iter, arg)?.clone());
         }
-        else if arg == "--switch-profile" {
-            read_profile = true;
+        else if arg == "--json-output" {
+            config.json_output = Some(next_value(&mut iter, arg)?.clone());
+        }
+        else if arg == "--name" {
+            let value = next_value(&mut iter, arg)?;
+            profile::encode_name(value)?;
+            config.profile_settings.name = Some(value.clone());
+        }
+        else if arg == "--dpi" {
+            let value = next_value(&mut iter, arg)?;
+            config.profile_settings.dpi = Some(parse_dpi_list(value)?);
+        }
+        else if arg == "--poll-rate" {
+            let value = next_value(&mut iter, arg)?;
+            let hz = match value.parse::<u16>() {
+                Ok(hz) => hz,
+                Err(_) => return Err(LogictlError::Parse(format!("invalid poll rate '{}'", value)))
+            };
+            profile::profile_rate_interval(hz)?;
+            config.profile_settings.report_rate = Some(hz);
+        }
+        else if arg == "--default-stage" {
+            let value = next_value(&mut iter, arg)?;
+            config.profile_settings.default_stage = Some(parse_stage(value)?);
+        }
+        else if arg == "--shift-dpi" {
+            let value = next_value(&mut iter, arg)?;
+            config.profile_settings.shift_dpi = match value.parse::<u16>() {
+                Ok(0) => return Err(LogictlError::Validation("shift dpi cannot be 0".to_string())),
+                Ok(dpi) => Some(dpi),
+                Err(_) => return Err(LogictlError::Parse(format!("invalid dpi '{}'", value)))
+            };
+        }
+        else if arg == "--dpi-stage" {
+            let value = next_value(&mut iter, arg)?;
+            config.dpi_stage = parse_stage(value)?;
         }
         else if arg == "--status" {
             config.print_status = true;
         }
+        else if arg == "--hidraw" {
+            config.use_hidraw = true;
+        }
+        else if arg == "--json" {
+            config.json = true;
+        }
+        else if config.command.is_none() && !arg.starts_with("-") {
+            config.command = Some(parse_command(arg, &mut iter)?);
+        }
+        else if config.input.is_none() && !arg.starts_with("-") && takes_input(&config.command) {
+            config.input = Some(arg.clone());
+        }
+        else {
+            return Err(LogictlError::Parse(format!("unknown argument '{}'", arg)));
+        }
+    }
+
+    if let Some(Command::ProfileDump) = config.command {
+        if config.profile == 0 {
+            return Err(LogictlError::Parse("profile dump needs --profile".to_string()));
+        }
+        if config.output.is_none() {
+            return Err(LogictlError::Parse("profile dump needs -o file".to_string()));
+        }
+    }
+    if let Some(Command::ProfileRestore) = config.command {
+        if config.profile == 0 {
+            return Err(LogictlError::Parse("profile restore needs --profile".to_string()));
+        }
+        if config.input.is_none() {
+            return Err(LogictlError::Parse("profile restore needs a file to read".to_string()));
+        }
+    }
+    if let Some(Command::Backup) = config.command {
+        if config.output.is_none() {
+            return Err(LogictlError::Parse("backup needs -o file".to_string()));
+        }
+    }
+    if let Some(Command::RestoreAll) = config.command {
+        if config.input.is_none() {
+            return Err(LogictlError::Parse("restore-all needs an archive to read".to_string()));
+        }
+    }
+    return Ok(config);
+}
+
+
+fn run(args: &[String]) -> Result<(), LogictlError> {
+    let config = parse_args(args)?;
+
+    session::install_signal_handlers();
+
+    // talk through hidraw, leaving the kernel driver attached
+    if config.use_hidraw {
+        let path = match HidrawTransport::find(config.vendor_id, config.product_id) {
+            Ok(path) => path,
+            Err(_) => return Err(LogictlError::DeviceNotFound)
+        };
+        let transport = HidrawTransport::open(&path)?;
+        let controller = UsbController::new(transport, false);
+        return run_commands(controller, &config);
     }
 
     // device selection
-    let mut context = libusb::Context::new().unwrap();
-    for mut device in context.devices().unwrap().iter() {
-        let device_desc = device.device_descriptor().unwrap();
+    let context = libusb::Context::new()?;
+    let mut found = false;
+    for device in context.devices()?.iter() {
+        let device_desc = device.device_descriptor()?;
         if device_desc.vendor_id() == config.vendor_id && device_desc.product_id() == config.product_id {
-            select_device(device, &config);
+            found = true;
+            select_device(device, &config)?;
         }
     }
+
+    if !found {
+        return Err(LogictlError::DeviceNotFound);
+    }
+    Ok(())
+}
+
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if let Err(err) = run(&args) {
+        eprintln!("Error: {}", err);
+        process::exit(err.exit_code());
+    }
 }
@@ -0,0 +1,943 @@
+use std::fmt;
+use serde::Serializer;
+use checksum;
+use error::LogictlError;
+
+// every onboard profile fills one sector
+pub const SECTOR_SIZE: usize = 256;
+
+// the profile directory
+pub const DIRECTORY_SECTOR: u16 = 0x0000;
+
+// read-only built in profiles, 0x0101 matches profile 1
+pub const ROM_SECTORS: u16 = 0x0100;
+
+// byte of a profile sector that must change on every write
+pub const WRITE_COUNTER_OFFSET: usize = 18;
+
+pub const DPI_COUNT: usize = 5;
+pub const BUTTON_COUNT: usize = 16;
+pub const LED_COUNT: usize = 2;
+
+// UTF-16LE characters in the name field
+pub const NAME_LENGTH: usize = 24;
+
+
+/**
+ * Report rate in hz from the interval in ms,
+ * which is how both profiles and the Report Rate feature store it
+ */
+pub fn rate_hz(interval: u8) -> u16 {
+    if interval == 0 {
+        return 0;
+    }
+    1000 / interval as u16
+}
+
+
+/**
+ * Interval in ms for a report rate in hz, None when no whole interval gives that rate
+ */
+pub fn rate_interval(hz: u16) -> Option<u8> {
+    (1..9u8).find(|&interval| rate_hz(interval) == hz)
+}
+
+
+/**
+ * Interval in ms a profile stores for a report rate in hz, profiles only go down to 250hz
+ */
+pub fn profile_rate_interval(hz: u16) -> Result<u8, LogictlError> {
+    match rate_interval(hz) {
+        Some(interval) if interval <= 4 => Ok(interval),
+        _ => Err(LogictlError::Validation(format!("profiles take a report rate of 1000, 500, 333 or 250 hz, not {}", hz)))
+    }
+}
+
+
+/**
+ * Who is in charge of dpi, rate and leds
+ * onboard applies the stored profiles, host lets software change them live
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnboardMode {
+    Onboard,
+    Host
+}
+
+
+impl OnboardMode {
+    pub fn from_byte(mode: u8) -> Result<OnboardMode, LogictlError> {
+        match mode {
+            0x01 => Ok(OnboardMode::Onboard),
+            0x02 => Ok(OnboardMode::Host),
+            _ => Err(LogictlError::Validation(format!("unknown onboard mode 0x{:02x}", mode)))
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            OnboardMode::Onboard => 0x01,
+            OnboardMode::Host => 0x02
+        }
+    }
+}
+
+
+impl fmt::Display for OnboardMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OnboardMode::Onboard => write!(f, "onboard"),
+            OnboardMode::Host => write!(f, "host")
+        }
+    }
+}
+
+
+/**
+ * What a button does, 4 bytes per button
+ * Anything that would not encode back to the same bytes is kept as Unknown
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ButtonBinding {
+    // 0x80 0x01, bitmask of mouse buttons
+    MouseButton(u16),
+
+    // 0x80 0x02, modifier bitmask and key usage
+    Key { modifiers: u8, key: u8 },
+
+    // 0x80 0x03, consumer control usage
+    Consumer(u16),
+
+    // 0x90, dpi and profile switching, wheel tilt and g-shift
+    Special(u8),
+
+    // 0x00, location of a macro in onboard memory
+    Macro { page: u8, offset: u8 },
+
+    // 0xff
+    Disabled,
+
+    Unknown([u8; 4])
+}
+
+
+impl ButtonBinding {
+    pub fn parse(bytes: &[u8]) -> ButtonBinding {
+        let raw = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        let binding = match (raw[0], raw[1]) {
+            (0x80, 0x01) => ButtonBinding::MouseButton(((raw[2] as u16) << 8) | raw[3] as u16),
+            (0x80, 0x02) => ButtonBinding::Key { modifiers: raw[2], key: raw[3] },
+            (0x80, 0x03) => ButtonBinding::Consumer(((raw[2] as u16) << 8) | raw[3] as u16),
+            (0x90, special) => ButtonBinding::Special(special),
+            (0x00, page) => ButtonBinding::Macro { page: page, offset: raw[3] },
+            (0xff, _) => ButtonBinding::Disabled,
+            _ => ButtonBinding::Unknown(raw)
+        };
+
+        // padding bytes that do not match what we write are kept as they were
+        if binding.to_bytes() == raw {
+            binding
+        }
+        else {
+            ButtonBinding::Unknown(raw)
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 4] {
+        match *self {
+            ButtonBinding::MouseButton(mask) => [0x80, 0x01, (mask >> 8) as u8, (mask & 0xff) as u8],
+            ButtonBinding::Key { modifiers, key } => [0x80, 0x02, modifiers, key],
+            ButtonBinding::Consumer(usage) => [0x80, 0x03, (usage >> 8) as u8, (usage & 0xff) as u8],
+            ButtonBinding::Special(special) => [0x90, special, 0xff, 0xff],
+            ButtonBinding::Macro { page, offset } => [0x00, page, 0x00, offset],
+            ButtonBinding::Disabled => [0xff, 0xff, 0xff, 0xff],
+            ButtonBinding::Unknown(raw) => raw
+        }
+    }
+}
+
+
+/**
+ * One lighting effect, 11 bytes each
+ * periods are big endian milliseconds
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LedEffect {
+    Off,
+    Static { r: u8, g: u8, b: u8 },
+    Cycle { period: u16, brightness: u8 },
+    Breathing { r: u8, g: u8, b: u8, period: u16, brightness: u8 },
+    Unknown([u8; 11])
+}
+
+
+impl LedEffect {
+    pub fn parse(bytes: &[u8]) -> LedEffect {
+        let mut raw = [0u8; 11];
+        raw.copy_from_slice(&bytes[..11]);
+
+        let effect = match raw[0] {
+            0x00 => LedEffect::Off,
+            0x01 => LedEffect::Static { r: raw[1], g: raw[2], b: raw[3] },
+            0x03 => LedEffect::Cycle {
+                period: ((raw[6] as u16) << 8) | raw[7] as u16,
+                brightness: raw[8]
+            },
+            0x0a => LedEffect::Breathing {
+                r: raw[1],
+                g: raw[2],
+                b: raw[3],
+                period: ((raw[4] as u16) << 8) | raw[5] as u16,
+                brightness: raw[7]
+            },
+            _ => LedEffect::Unknown(raw)
+        };
+
+        if effect.to_bytes() == raw {
+            effect
+        }
+        else {
+            LedEffect::Unknown(raw)
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 11] {
+        let mut raw = [0u8; 11];
+        match *self {
+            LedEffect::Off => {},
+            LedEffect::Static { r, g, b } => {
+                raw[0] = 0x01;
+                raw[1] = r;
+                raw[2] = g;
+                raw[3] = b;
+            },
+            LedEffect::Cycle { period, brightness } => {
+                raw[0] = 0x03;
+                raw[6] = (period >> 8) as u8;
+                raw[7] = (period & 0xff) as u8;
+                raw[8] = brightness;
+            },
+            LedEffect::Breathing { r, g, b, period, brightness } => {
+                raw[0] = 0x0a;
+                raw[1] = r;
+                raw[2] = g;
+                raw[3] = b;
+                raw[4] = (period >> 8) as u8;
+                raw[5] = (period & 0xff) as u8;
+                raw[7] = brightness;
+            },
+            LedEffect::Unknown(bytes) => raw = bytes
+        }
+        return raw;
+    }
+}
+
+
+/**
+ * The 256 byte profile sector
+ *
+ * 0        report interval in ms, 1 = 1000hz, 2 = 500hz, 3 = 333hz, 4 = 250hz
+ * 1        default dpi index
+ * 2        shift dpi index
+ * 3..13    dpi x5, little endian, 0 disables a slot
+ * 13..16   profile color
+ * 16       power mode
+ * 17       angle snapping
+ * 18       write counter, must change on every write
+ * 19..28   reserved
+ * 28..32   power save and power off timeouts, little endian
+ * 32..96   buttons
+ * 96..160  buttons while g-shift is held
+ * 160..208 name, UTF-16LE
+ * 208..252 leds, then leds in power save mode
+ * 252..254 free
+ * 254..256 crc, big endian
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OnboardProfile {
+    pub report_rate: u8,
+    pub default_dpi_index: u8,
+    pub shift_dpi_index: u8,
+    pub dpi: [u16; DPI_COUNT],
+    pub color: [u8; 3],
+    pub power_mode: u8,
+    pub angle_snapping: u8,
+    pub write_counter: u8,
+    pub reserved: [u8; 9],
+    pub power_save_timeout: u16,
+    pub power_off_timeout: u16,
+    pub buttons: [ButtonBinding; BUTTON_COUNT],
+    pub shift_buttons: [ButtonBinding; BUTTON_COUNT],
+    #[serde(serialize_with = "serialize_name")]
+    pub name: [u16; NAME_LENGTH],
+    pub leds: [LedEffect; LED_COUNT],
+    pub power_save_leds: [LedEffect; LED_COUNT],
+    pub free: [u8; 2],
+    pub crc: u16
+}
+
+
+fn decode_name(name: &[u16]) -> String {
+    let len = name.iter().position(|&c| c == 0x0000 || c == 0xffff).unwrap_or(name.len());
+    String::from_utf16_lossy(&name[..len])
+}
+
+
+/**
+ * Name as stored in a profile, utf-16 padded with 0x0000
+ */
+pub fn encode_name(name: &str) -> Result<[u16; NAME_LENGTH], LogictlError> {
+    let encoded: Vec<u16> = name.encode_utf16().collect();
+    if encoded.len() > NAME_LENGTH {
+        return Err(LogictlError::Validation(format!("profile name can be at most {} characters, '{}' has {}", NAME_LENGTH, name, encoded.len())));
+    }
+    if encoded.contains(&0x0000) {
+        return Err(LogictlError::Validation("profile name cannot contain a null character".to_string()));
+    }
+
+    let mut stored = [0x0000; NAME_LENGTH];
+    stored[..encoded.len()].copy_from_slice(&encoded);
+    Ok(stored)
+}
+
+
+fn serialize_name<S: Serializer>(name: &[u16; NAME_LENGTH], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&decode_name(name))
+}
+
+
+fn read_u16_le(bytes: &[u8], at: usize) -> u16 {
+    bytes[at] as u16 | ((bytes[at + 1] as u16) << 8)
+}
+
+
+fn write_u16_le(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at] = (value & 0xff) as u8;
+    bytes[at + 1] = (value >> 8) as u8;
+}
+
+
+impl OnboardProfile {
+
+    /**
+     * Decodes a whole sector, the crc is kept as read
+     */
+    pub fn parse(bytes: &[u8]) -> Result<OnboardProfile, LogictlError> {
+        if bytes.len() != SECTOR_SIZE {
+            return Err(LogictlError::Validation(format!("profile sector must be {} bytes, got {}", SECTOR_SIZE, bytes.len())));
+        }
+
+        let mut dpi = [0u16; DPI_COUNT];
+        for i in 0..DPI_COUNT {
+            dpi[i] = read_u16_le(bytes, 3 + 2 * i);
+        }
+
+        let mut buttons = [ButtonBinding::Disabled; BUTTON_COUNT];
+        let mut shift_buttons = [ButtonBinding::Disabled; BUTTON_COUNT];
+        for i in 0..BUTTON_COUNT {
+            buttons[i] = ButtonBinding::parse(&bytes[32 + 4 * i..]);
+            shift_buttons[i] = ButtonBinding::parse(&bytes[96 + 4 * i..]);
+        }
+
+        let mut name = [0u16; NAME_LENGTH];
+        for i in 0..NAME_LENGTH {
+            name[i] = read_u16_le(bytes, 160 + 2 * i);
+        }
+
+        let mut leds = [LedEffect::Off; LED_COUNT];
+        let mut power_save_leds = [LedEffect::Off; LED_COUNT];
+        for i in 0..LED_COUNT {
+            leds[i] = LedEffect::parse(&bytes[208 + 11 * i..]);
+            power_save_leds[i] = LedEffect::parse(&bytes[230 + 11 * i..]);
+        }
+
+        let mut reserved = [0u8; 9];
+        reserved.copy_from_slice(&bytes[19..28]);
+
+        return Ok(OnboardProfile {
+            report_rate: bytes[0],
+            default_dpi_index: bytes[1],
+            shift_dpi_index: bytes[2],
+            dpi: dpi,
+            color: [bytes[13], bytes[14], bytes[15]],
+            power_mode: bytes[16],
+            angle_snapping: bytes[17],
+            write_counter: bytes[WRITE_COUNTER_OFFSET],
+            reserved: reserved,
+            power_save_timeout: read_u16_le(bytes, 28),
+            power_off_timeout: read_u16_le(bytes, 30),
+            buttons: buttons,
+            shift_buttons: shift_buttons,
+            name: name,
+            leds: leds,
+            power_save_leds: power_save_leds,
+            free: [bytes[252], bytes[253]],
+            crc: ((bytes[254] as u16) << 8) | bytes[255] as u16
+        });
+    }
+
+    /**
+     * Encodes the sector with the crc field as it is
+     */
+    pub fn to_bytes(&self) -> [u8; SECTOR_SIZE] {
+        let mut bytes = [0u8; SECTOR_SIZE];
+        bytes[0] = self.report_rate;
+        bytes[1] = self.default_dpi_index;
+        bytes[2] = self.shift_dpi_index;
+        for i in 0..DPI_COUNT {
+            write_u16_le(&mut bytes, 3 + 2 * i, self.dpi[i]);
+        }
+        bytes[13..16].copy_from_slice(&self.color);
+        bytes[16] = self.power_mode;
+        bytes[17] = self.angle_snapping;
+        bytes[WRITE_COUNTER_OFFSET] = self.write_counter;
+        bytes[19..28].copy_from_slice(&self.reserved);
+        write_u16_le(&mut bytes, 28, self.power_save_timeout);
+        write_u16_le(&mut bytes, 30, self.power_off_timeout);
+
+        for i in 0..BUTTON_COUNT {
+            bytes[32 + 4 * i..36 + 4 * i].copy_from_slice(&self.buttons[i].to_bytes());
+            bytes[96 + 4 * i..100 + 4 * i].copy_from_slice(&self.shift_buttons[i].to_bytes());
+        }
+
+        for i in 0..NAME_LENGTH {
+            write_u16_le(&mut bytes, 160 + 2 * i, self.name[i]);
+        }
+
+        for i in 0..LED_COUNT {
+            bytes[208 + 11 * i..219 + 11 * i].copy_from_slice(&self.leds[i].to_bytes());
+            bytes[230 + 11 * i..241 + 11 * i].copy_from_slice(&self.power_save_leds[i].to_bytes());
+        }
+
+        bytes[252..254].copy_from_slice(&self.free);
+        bytes[254] = (self.crc >> 8) as u8;
+        bytes[255] = (self.crc & 0xff) as u8;
+        return bytes;
+    }
+
+    /**
+     * The name up to the first null character
+     */
+    pub fn name(&self) -> String {
+        decode_name(&self.name)
+    }
+
+    /**
+     * Stores a name of up to 24 UTF-16 characters, the rest is zeroed
+     */
+    pub fn set_name(&mut self, name: &str) -> Result<(), LogictlError> {
+        self.name = encode_name(name)?;
+        Ok(())
+    }
+}
+
+
+/**
+ * Profile fields to change, anything left as None keeps its stored value
+ */
+pub struct ProfileSettings {
+    pub report_rate: Option<u16>,
+    pub dpi: Option<Vec<u16>>,
+    pub default_stage: Option<u8>,
+    pub shift_dpi: Option<u16>,
+    pub name: Option<String>
+}
+
+
+impl ProfileSettings {
+    pub fn new() -> ProfileSettings {
+        ProfileSettings {
+            report_rate: None,
+            dpi: None,
+            default_stage: None,
+            shift_dpi: None,
+            name: None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.report_rate.is_none() && self.dpi.is_none() && self.default_stage.is_none()
+            && self.shift_dpi.is_none() && self.name.is_none()
+    }
+
+    /**
+     * Merges the changes into a profile read from the device
+     */
+    pub fn apply(&self, profile: &mut OnboardProfile) -> Result<(), LogictlError> {
+        if let Some(hz) = self.report_rate {
+            profile.report_rate = profile_rate_interval(hz)?;
+        }
+
+        // unused stages are disabled with 0
+        if let Some(ref dpi) = self.dpi {
+            if dpi.is_empty() || dpi.len() > DPI_COUNT {
+                return Err(LogictlError::Validation(format!("a profile takes 1-{} dpi stages, got {}", DPI_COUNT, dpi.len())));
+            }
+            profile.dpi = [0; DPI_COUNT];
+            profile.dpi[..dpi.len()].copy_from_slice(dpi);
+        }
+
+        if let Some(stage) = self.default_stage {
+            let index = (stage as usize).wrapping_sub(1);
+            if index >= DPI_COUNT || profile.dpi[index] == 0 {
+                return Err(LogictlError::Validation(format!("default stage {} has no dpi set", stage)));
+            }
+            profile.default_dpi_index = index as u8;
+        }
+        else if profile.dpi[profile.default_dpi_index as usize % DPI_COUNT] == 0 {
+
+            // a shorter dpi list can leave the default pointing at a disabled stage
+            profile.default_dpi_index = 0;
+        }
+
+        // 0 would match a disabled stage
+        if let Some(shift_dpi) = self.shift_dpi {
+            match profile.dpi.iter().position(|&dpi| dpi == shift_dpi && dpi != 0) {
+                Some(index) => profile.shift_dpi_index = index as u8,
+                None => return Err(LogictlError::Validation(format!("shift dpi {} must be one of the dpi stages {:?}", shift_dpi, profile.dpi)))
+            }
+        }
+        else if profile.dpi[profile.shift_dpi_index as usize % DPI_COUNT] == 0 {
+            profile.shift_dpi_index = 0;
+        }
+
+        if let Some(ref name) = self.name {
+            profile.set_name(name)?;
+        }
+        Ok(())
+    }
+}
+
+
+/**
+ * One slot of the profile directory, 4 bytes each
+ * [sector hi, sector lo, enabled, 0x00]
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DirectoryEntry {
+    pub sector: u16,
+    pub enabled: bool
+}
+
+
+/**
+ * Sector 0, which lists the sector behind each profile slot
+ * the list ends with 0xffff and the rest of the sector is 0xff
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProfileDirectory {
+    pub entries: Vec<DirectoryEntry>
+}
+
+
+// sector marking the end of the directory
+const DIRECTORY_END: u16 = 0xffff;
+
+// entries that fit in front of the end marker and crc
+const MAX_DIRECTORY_ENTRIES: usize = (SECTOR_SIZE - 2 - 4) / 4;
+
+
+impl ProfileDirectory {
+    pub fn parse(bytes: &[u8]) -> Result<ProfileDirectory, LogictlError> {
+        if bytes.len() != SECTOR_SIZE {
+            return Err(LogictlError::Validation(format!("directory sector must be {} bytes, got {}", SECTOR_SIZE, bytes.len())));
+        }
+
+        // a full directory still has room for the end marker
+        let mut entries = Vec::new();
+        for entry in bytes[..(MAX_DIRECTORY_ENTRIES + 1) * 4].chunks_exact(4) {
+            let sector = ((entry[0] as u16) << 8) | entry[1] as u16;
+            if sector == DIRECTORY_END {
+                return Ok(ProfileDirectory {
+                    entries: entries
+                });
+            }
+            entries.push(DirectoryEntry {
+                sector: sector,
+                enabled: entry[2] != 0x00
+            });
+        }
+        Err(LogictlError::Validation(format!("directory sector has no end marker in its first {} entries", MAX_DIRECTORY_ENTRIES + 1)))
+    }
+
+    /**
+     * Encodes the directory with a freshly computed crc
+     */
+    pub fn to_bytes(&self) -> Result<[u8; SECTOR_SIZE], LogictlError> {
+        if self.entries.len() > MAX_DIRECTORY_ENTRIES {
+            return Err(LogictlError::Validation(format!("directory holds at most {} profiles", MAX_DIRECTORY_ENTRIES)));
+        }
+
+        let mut bytes = [0xffu8; SECTOR_SIZE];
+        let mut at = 0;
+        for entry in &self.entries {
+            bytes[at] = (entry.sector >> 8) as u8;
+            bytes[at + 1] = (entry.sector & 0xff) as u8;
+            bytes[at + 2] = if entry.enabled { 0x01 } else { 0x00 };
+            bytes[at + 3] = 0x00;
+            at += 4;
+        }
+        bytes[at + 2] = 0x00;
+        bytes[at + 3] = 0x00;
+
+        checksum::seal(&mut bytes);
+        return Ok(bytes);
+    }
+
+    fn missing_slot(&self, slot: u8) -> LogictlError {
+        LogictlError::Validation(format!("profile {} is not in the directory, which has {} slots", slot, self.entries.len()))
+    }
+
+    /**
+     * The entry for a profile slot, counting from 1
+     */
+    pub fn slot_mut(&mut self, slot: u8) -> Result<&mut DirectoryEntry, LogictlError> {
+        let err = self.missing_slot(slot);
+        self.entries.get_mut((slot as usize).wrapping_sub(1)).ok_or(err)
+    }
+
+    /**
+     * The sector behind a profile slot, counting from 1
+     */
+    pub fn sector(&self, slot: u8) -> Result<u16, LogictlError> {
+        match self.entries.get((slot as usize).wrapping_sub(1)) {
+            Some(entry) => Ok(entry.sector),
+            None => Err(self.missing_slot(slot))
+        }
+    }
+
+    /**
+     * The slot pointing at a sector, counting from 1
+     */
+    pub fn slot(&self, sector: u16) -> Option<u8> {
+        self.entries.iter().position(|entry| entry.sector == sector).map(|index| index as u8 + 1)
+    }
+
+    /**
+     * Points a slot at a sector, a slot right after the last one is added
+     */
+    pub fn set_sector(&mut self, slot: u8, sector: u16) -> Result<(), LogictlError> {
+        if sector == 0x0000 || sector == DIRECTORY_END {
+            return Err(LogictlError::Validation(format!("sector 0x{:04x} cannot hold a profile", sector)));
+        }
+        if slot as usize == self.entries.len() + 1 {
+            self.entries.push(DirectoryEntry {
+                sector: sector,
+                enabled: true
+            });
+            return Ok(());
+        }
+        self.slot_mut(slot)?.sector = sector;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use checksum;
+    use error::LogictlError;
+    use super::*;
+
+    /**
+     * A profile sector with every field set to something recognisable
+     */
+    fn sample_sector() -> [u8; SECTOR_SIZE] {
+        let mut bytes = [0xffu8; SECTOR_SIZE];
+        bytes[0] = 0x01;
+        bytes[1] = 0x01;
+        bytes[2] = 0x00;
+        let dpi: [u16; DPI_COUNT] = [400, 800, 1600, 3200, 0];
+        for i in 0..DPI_COUNT {
+            write_u16_le(&mut bytes, 3 + 2 * i, dpi[i]);
+        }
+        bytes[18] = 0x07;
+        bytes[32..36].copy_from_slice(&[0x80, 0x01, 0x00, 0x01]);
+        bytes[36..40].copy_from_slice(&[0x80, 0x02, 0x02, 0x04]);
+        bytes[40..44].copy_from_slice(&[0x80, 0x03, 0x00, 0xe9]);
+        bytes[44..48].copy_from_slice(&[0x90, 0x05, 0xff, 0xff]);
+        bytes[48..52].copy_from_slice(&[0x00, 0x06, 0x00, 0x10]);
+        for (i, c) in "Test".encode_utf16().enumerate() {
+            write_u16_le(&mut bytes, 160 + 2 * i, c);
+        }
+        write_u16_le(&mut bytes, 168, 0x0000);
+        bytes[208..219].copy_from_slice(&[0x01, 0xff, 0x80, 0x00, 0, 0, 0, 0, 0, 0, 0]);
+        bytes[219..230].copy_from_slice(&[0x0a, 0x00, 0xff, 0x00, 0x0f, 0xa0, 0, 0x64, 0, 0, 0]);
+        bytes[254] = 0x12;
+        bytes[255] = 0x34;
+        bytes
+    }
+
+    /**
+     * The profile the mouse ships with, as read back from sector 1
+     */
+    fn template_sector() -> [u8; SECTOR_SIZE] {
+        let rows: [[u8; 16]; 16] = [
+            [0x01, 0x01, 0x00, 0x90, 0x01, 0x8a, 0x02, 0x84, 0x03, 0x7e, 0x04, 0x00, 0x00, 0xff, 0xff, 0xff],
+            [0xff, 0x00, 0x1d, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
+            [0x80, 0x01, 0x00, 0x10, 0x90, 0x05, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            [0xff; 16],
+            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
+            [0x80, 0x01, 0x00, 0x01, 0x80, 0x01, 0x00, 0x02, 0x80, 0x01, 0x00, 0x04, 0x80, 0x01, 0x00, 0x08],
+            [0x80, 0x01, 0x00, 0x10, 0x90, 0x05, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            [0x00; 16],
+            [0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00, 0x00],
+            [0x50, 0x00, 0x72, 0x00, 0x6f, 0x00, 0x66, 0x00, 0x69, 0x00, 0x6c, 0x00, 0x65, 0x00, 0x20, 0x00],
+            [0x31, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            [0x00; 16],
+            [0x01, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00, 0x00],
+            [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xcd, 0xc4]
+        ];
+
+        let mut bytes = [0u8; SECTOR_SIZE];
+        for (i, row) in rows.iter().enumerate() {
+            bytes[16 * i..16 * (i + 1)].copy_from_slice(row);
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_fields() {
+        let profile = OnboardProfile::parse(&sample_sector()).unwrap();
+        assert_eq!(profile.report_rate, 0x01);
+        assert_eq!(profile.default_dpi_index, 1);
+        assert_eq!(profile.dpi, [400, 800, 1600, 3200, 0]);
+        assert_eq!(profile.write_counter, 0x07);
+        assert_eq!(profile.name(), "Test");
+        assert_eq!(profile.buttons[0], ButtonBinding::MouseButton(0x0001));
+        assert_eq!(profile.buttons[1], ButtonBinding::Key { modifiers: 0x02, key: 0x04 });
+        assert_eq!(profile.buttons[2], ButtonBinding::Consumer(0x00e9));
+        assert_eq!(profile.buttons[3], ButtonBinding::Special(0x05));
+        assert_eq!(profile.buttons[4], ButtonBinding::Macro { page: 0x06, offset: 0x10 });
+        assert_eq!(profile.buttons[5], ButtonBinding::Disabled);
+        assert_eq!(profile.leds[0], LedEffect::Static { r: 0xff, g: 0x80, b: 0x00 });
+        assert_eq!(profile.leds[1], LedEffect::Breathing { r: 0x00, g: 0xff, b: 0x00, period: 4000, brightness: 100 });
+        assert_eq!(profile.crc, 0x1234);
+    }
+
+    #[test]
+    fn parses_the_shipped_profile() {
+        let profile = OnboardProfile::parse(&template_sector()).unwrap();
+        assert_eq!(profile.dpi, [400, 650, 900, 1150, 0]);
+        assert_eq!(profile.write_counter, 0x1d);
+        assert_eq!(profile.name(), "Profile 1");
+        assert_eq!(profile.buttons[5], ButtonBinding::Special(0x05));
+        assert_eq!(profile.leds[0], LedEffect::Static { r: 0xff, g: 0x00, b: 0x00 });
+    }
+
+    #[test]
+    fn wrong_sector_length_is_rejected() {
+        assert!(OnboardProfile::parse(&[0u8; 255]).is_err());
+    }
+
+    #[test]
+    fn bindings_with_odd_padding_stay_unknown() {
+        let raw = [0x90, 0x05, 0x00, 0x00];
+        assert_eq!(ButtonBinding::parse(&raw), ButtonBinding::Unknown(raw));
+        let raw = [0xff, 0x00, 0x00, 0x00];
+        assert_eq!(ButtonBinding::parse(&raw), ButtonBinding::Unknown(raw));
+
+        let mut led = [0u8; 11];
+        led[0] = 0x01;
+        led[10] = 0x01;
+        assert_eq!(LedEffect::parse(&led), LedEffect::Unknown(led));
+    }
+
+    #[test]
+    fn sectors_round_trip() {
+        // bindings and effects that only survive as Unknown
+        let mut odd = sample_sector();
+        odd[52..56].copy_from_slice(&[0x90, 0x05, 0x00, 0x00]);
+        odd[56..60].copy_from_slice(&[0x80, 0x07, 0x12, 0x34]);
+        odd[96..100].copy_from_slice(&[0x00, 0x06, 0x01, 0x10]);
+        odd[100..104].copy_from_slice(&[0xff, 0x00, 0x00, 0x00]);
+        odd[230..241].copy_from_slice(&[0x03, 0, 0, 0, 0, 0, 0x27, 0x10, 0x64, 0, 0]);
+        odd[241..252].copy_from_slice(&[0x02, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa]);
+
+        let sectors = [sample_sector(), template_sector(), odd, [0xffu8; SECTOR_SIZE], [0x00u8; SECTOR_SIZE]];
+        for bytes in sectors.iter() {
+            let profile = OnboardProfile::parse(bytes).unwrap();
+            assert_eq!(&profile.to_bytes()[..], &bytes[..]);
+        }
+    }
+
+    #[test]
+    fn directory_round_trips() {
+        let mut bytes = [0xffu8; SECTOR_SIZE];
+        bytes[..12].copy_from_slice(&[0x00, 0x01, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x01, 0x03, 0x01, 0x00]);
+        bytes[12..16].copy_from_slice(&[0xff, 0xff, 0x00, 0x00]);
+        checksum::seal(&mut bytes);
+
+        let directory = ProfileDirectory::parse(&bytes).unwrap();
+        assert_eq!(directory.entries, vec![
+            DirectoryEntry { sector: 0x0001, enabled: true },
+            DirectoryEntry { sector: 0x0002, enabled: false },
+            DirectoryEntry { sector: 0x0103, enabled: true }
+        ]);
+        assert_eq!(&directory.to_bytes().unwrap()[..], &bytes[..]);
+    }
+
+    #[test]
+    fn full_directory_round_trips() {
+        let directory = ProfileDirectory {
+            entries: (1..MAX_DIRECTORY_ENTRIES as u16 + 1).map(|sector| DirectoryEntry { sector: sector, enabled: true }).collect()
+        };
+        let bytes = directory.to_bytes().unwrap();
+        assert_eq!(ProfileDirectory::parse(&bytes).unwrap(), directory);
+
+        let mut too_many = directory.clone();
+        too_many.entries.push(DirectoryEntry { sector: 0x0040, enabled: true });
+        assert!(too_many.to_bytes().is_err());
+    }
+
+    #[test]
+    fn directory_slots() {
+        let mut directory = ProfileDirectory {
+            entries: vec![DirectoryEntry { sector: 0x0001, enabled: true }]
+        };
+        directory.set_sector(2, 0x0004).unwrap();
+        directory.set_sector(1, 0x0003).unwrap();
+        assert_eq!(directory.entries[0].sector, 0x0003);
+        assert_eq!(directory.entries[1].sector, 0x0004);
+
+        assert!(directory.set_sector(4, 0x0005).is_err());
+        assert!(directory.set_sector(1, 0x0000).is_err());
+        assert!(directory.slot_mut(0).is_err());
+        assert!(directory.slot_mut(3).is_err());
+
+        assert_eq!(directory.sector(2).unwrap(), 0x0004);
+        assert!(directory.sector(3).is_err());
+        assert_eq!(directory.slot(0x0004), Some(2));
+        assert_eq!(directory.slot(0x0001), None);
+    }
+
+    /**
+     * Sector 0 as the device wrote it with only the given slot enabled
+     */
+    fn directory_sector(enabled_slot: u8) -> [u8; SECTOR_SIZE] {
+        let mut bytes = [0xffu8; SECTOR_SIZE];
+        for slot in 1..6 {
+            let at = 4 * (slot as usize - 1);
+            let enabled = if slot == enabled_slot { 0x01 } else { 0x00 };
+            bytes[at..at + 4].copy_from_slice(&[0x00, slot, enabled, 0x00]);
+        }
+        bytes[20..24].copy_from_slice(&[0xff, 0xff, 0x00, 0x00]);
+        bytes
+    }
+
+    #[test]
+    fn crcs_match_what_the_device_wrote() {
+        let profile = template_sector();
+        assert_eq!(checksum::compute(&profile), 0xcdc4);
+        assert!(checksum::is_valid(&profile));
+
+        let mut slot_2 = directory_sector(2);
+        checksum::seal(&mut slot_2);
+        assert_eq!(&slot_2[254..], &[0xe5, 0xda]);
+
+        let mut slot_3 = directory_sector(3);
+        checksum::seal(&mut slot_3);
+        assert_eq!(&slot_3[254..], &[0x3c, 0xe2]);
+        assert_eq!(&ProfileDirectory::parse(&slot_3).unwrap().to_bytes().unwrap()[..], &slot_3[..]);
+    }
+
+    #[test]
+    fn name_is_utf16_and_limited() {
+        let mut profile = OnboardProfile::parse(&sample_sector()).unwrap();
+        profile.set_name("Ünïcode").unwrap();
+        assert_eq!(profile.name(), "Ünïcode");
+        assert_eq!(profile.name[7], 0x0000);
+
+        assert!(profile.set_name(&"x".repeat(NAME_LENGTH)).is_ok());
+        assert!(profile.set_name(&"x".repeat(NAME_LENGTH + 1)).is_err());
+        assert!(profile.set_name("a\0b").is_err());
+    }
+
+    #[test]
+    fn rates() {
+        assert_eq!(rate_hz(1), 1000);
+        assert_eq!(rate_hz(3), 333);
+        assert_eq!(rate_hz(0), 0);
+        assert_eq!(rate_interval(500), Some(2));
+        assert_eq!(rate_interval(125), Some(8));
+        assert_eq!(rate_interval(300), None);
+        assert_eq!(profile_rate_interval(250).unwrap(), 4);
+        assert!(profile_rate_interval(125).is_err());
+    }
+
+    #[test]
+    fn settings_change_only_what_is_given() {
+        let before = OnboardProfile::parse(&sample_sector()).unwrap();
+        let mut profile = before.clone();
+        let mut settings = ProfileSettings::new();
+        settings.report_rate = Some(500);
+        settings.apply(&mut profile).unwrap();
+
+        assert_eq!(profile.report_rate, 0x02);
+        profile.report_rate = before.report_rate;
+        assert_eq!(profile, before);
+
+        settings.report_rate = Some(125);
+        assert!(settings.apply(&mut profile).is_err());
+    }
+
+    #[test]
+    fn shorter_dpi_list_moves_stages_off_disabled_slots() {
+        let mut profile = OnboardProfile::parse(&sample_sector()).unwrap();
+        profile.default_dpi_index = 3;
+        profile.shift_dpi_index = 2;
+
+        let mut settings = ProfileSettings::new();
+        settings.dpi = Some(vec![800, 1600]);
+        settings.apply(&mut profile).unwrap();
+
+        assert_eq!(profile.dpi, [800, 1600, 0, 0, 0]);
+        assert_eq!(profile.default_dpi_index, 0);
+        assert_eq!(profile.shift_dpi_index, 0);
+
+        settings.dpi = Some(vec![]);
+        assert!(settings.apply(&mut profile).is_err());
+    }
+
+    #[test]
+    fn default_stage_must_have_a_dpi() {
+        let mut profile = OnboardProfile::parse(&sample_sector()).unwrap();
+        let mut settings = ProfileSettings::new();
+
+        settings.default_stage = Some(4);
+        settings.apply(&mut profile).unwrap();
+        assert_eq!(profile.default_dpi_index, 3);
+
+        settings.default_stage = Some(5);
+        assert!(settings.apply(&mut profile).is_err());
+        settings.default_stage = Some(0);
+        assert!(settings.apply(&mut profile).is_err());
+    }
+
+    #[test]
+    fn directory_without_end_marker_is_rejected() {
+        let mut bytes = [0u8; SECTOR_SIZE];
+        checksum::seal(&mut bytes);
+        match ProfileDirectory::parse(&bytes) {
+            Err(LogictlError::Validation(_)) => {},
+            other => panic!("expected a validation error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn shift_dpi_must_be_an_enabled_stage() {
+        let mut profile = OnboardProfile::parse(&sample_sector()).unwrap();
+        let mut settings = ProfileSettings::new();
+
+        settings.shift_dpi = Some(1600);
+        settings.apply(&mut profile).unwrap();
+        assert_eq!(profile.shift_dpi_index, 2);
+
+        settings.shift_dpi = Some(0);
+        assert!(settings.apply(&mut profile).is_err());
+        settings.shift_dpi = Some(1000);
+        assert!(settings.apply(&mut profile).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use libc;
+use controller::UsbController;
+use transport::Transport;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+
+/**
+ * Catches SIGINT and SIGTERM so the device can be released before exiting
+ * Commands poll interrupted() and stop at the next safe point
+ */
+pub fn install_signal_handlers() {
+    unsafe {
+        libc::signal(libc::SIGINT, handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handle_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+
+/**
+ * True once a termination signal has been received
+ */
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+
+/**
+ * Holds the device claimed for as long as it lives
+ * Dropping the session releases the interface and reattaches the kernel driver,
+ * which also happens when a command panics part way through
+ */
+pub struct Session<T: Transport> {
+    controller: UsbController<T>,
+    claimed: bool
+}
+
+
+impl<T: Transport> Session<T> {
+    pub fn open(mut controller: UsbController<T>) -> io::Result<Session<T>> {
+
+        // a half done claim may have detached the kernel driver, releasing gives it back
+        if let Err(err) = controller.claim() {
+            let _ = controller.release();
+            return Err(err);
+        }
+        return Ok(Session {
+            controller: controller,
+            claimed: true
+        });
+    }
+
+    /**
+     * Releases the device, reporting any error instead of printing it
+     */
+    pub fn close(mut self) -> io::Result<()> {
+        self.claimed = false;
+        self.controller.release()
+    }
+}
+
+
+impl<T: Transport> Deref for Session<T> {
+    type Target = UsbController<T>;
+
+    fn deref(&self) -> &UsbController<T> {
+        &self.controller
+    }
+}
+
+
+impl<T: Transport> DerefMut for Session<T> {
+    fn deref_mut(&mut self) -> &mut UsbController<T> {
+        &mut self.controller
+    }
+}
+
+
+impl<T: Transport> Drop for Session<T> {
+    fn drop(&mut self) {
+        if self.claimed {
+            self.claimed = false;
+            if let Err(err) = self.controller.release() {
+                eprintln!("Failed to release device: {}", err);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io;
+    use std::panic;
+    use std::rc::Rc;
+    use controller::UsbController;
+    use transport::mock::MockTransport;
+    use super::*;
+
+    struct Counts {
+        claims: Rc<Cell<usize>>,
+        releases: Rc<Cell<usize>>
+    }
+
+    fn open_session() -> (Session<MockTransport>, Counts) {
+        let (result, counts) = try_open(false);
+        return (result.unwrap(), counts);
+    }
+
+    fn try_open(fail_claim: bool) -> (io::Result<Session<MockTransport>>, Counts) {
+        let mut transport = MockTransport::new(vec![]);
+        transport.fail_claim = fail_claim;
+        let counts = Counts { claims: transport.claims.clone(), releases: transport.releases.clone() };
+        return (Session::open(UsbController::new(transport, false)), counts);
+    }
+
+    #[test]
+    fn close_releases_once() {
+        let (session, counts) = open_session();
+        assert_eq!((counts.claims.get(), counts.releases.get()), (1, 0));
+
+        session.close().unwrap();
+        assert_eq!(counts.releases.get(), 1);
+    }
+
+    #[test]
+    fn drop_releases_after_an_error() {
+        let (session, counts) = open_session();
+        let failing = |_session: Session<MockTransport>| -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::Other, "command failed"))
+        };
+
+        assert!(failing(session).is_err());
+        assert_eq!(counts.releases.get(), 1);
+    }
+
+    #[test]
+    fn drop_releases_after_a_panic() {
+        let (session, counts) = open_session();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(move || {
+            let _session = session;
+            panic!("command panicked");
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(counts.releases.get(), 1);
+    }
+
+    #[test]
+    fn failed_claim_gives_the_driver_back() {
+        let (result, counts) = try_open(true);
+
+        assert!(result.is_err());
+        assert_eq!((counts.claims.get(), counts.releases.get()), (1, 1));
+    }
+}
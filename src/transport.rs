@@ -0,0 +1,234 @@
+use std::io;
+use std::time::Duration;
+use libusb;
+
+/**
+ * Moves HID++ reports between the host and the device.
+ * The controller only deals in reports, so any backend able to
+ * send and receive them can be used.
+ */
+pub trait Transport {
+
+    /**
+     * Sends a 7 byte short report (0x10)
+     */
+    fn send_short(&mut self, msg: &[u8; 7]) -> io::Result<()>;
+
+    /**
+     * Sends a 20 byte long report (0x11)
+     */
+    fn send_long(&mut self, msg: &[u8; 20]) -> io::Result<()>;
+
+    /**
+     * Reads a single report into out
+     * returns the number of bytes read, or 0 if nothing arrived before the timeout
+     */
+    fn read_report(&mut self, out: &mut [u8], timeout: Duration) -> io::Result<usize>;
+
+    /**
+     * Takes exclusive access to the device, if the backend needs it
+     */
+    fn claim(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /**
+     * Gives back access taken by claim
+     */
+    fn release(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+/**
+ * Maps libusb errors onto the closest io error kind
+ */
+pub fn usb_error(err: libusb::Error) -> io::Error {
+    let kind = match err {
+        libusb::Error::Access => io::ErrorKind::PermissionDenied,
+        libusb::Error::NoDevice => io::ErrorKind::NotFound,
+        libusb::Error::NotFound => io::ErrorKind::NotFound,
+        libusb::Error::Timeout => io::ErrorKind::TimedOut,
+        libusb::Error::Interrupted => io::ErrorKind::Interrupted,
+        libusb::Error::InvalidParam => io::ErrorKind::InvalidInput,
+        _ => io::ErrorKind::Other
+    };
+    return io::Error::new(kind, err);
+}
+
+
+/**
+ * Talks to the device through libusb control and interrupt transfers
+ * The kernel driver is detached from the interface while claimed
+ */
+pub struct LibusbTransport<'a> {
+    handle: libusb::DeviceHandle<'a>,
+    interface: u8,
+    read_address: u8,
+    detached_kernel_driver: bool
+}
+
+
+impl<'a> LibusbTransport<'a> {
+    pub fn open(device: &libusb::Device<'a>, print_messages: bool) -> libusb::Result<LibusbTransport<'a>> {
+
+        let mut selected_interface = 0x00;
+        let mut selected_read_address = 0x82;
+        let mut selected_write_address = 0x00;
+
+        let config = device.active_config_descriptor()?;
+        for interface in config.interfaces() {
+            selected_interface = interface.number();
+            for descriptor in interface.descriptors() {
+                for endpoint in descriptor.endpoint_descriptors() {
+                    if endpoint.direction() == libusb::Direction::In {
+                        selected_read_address = endpoint.address();
+                    }
+                    else {
+                        selected_write_address = endpoint.address();
+                    }
+                }
+            }
+        }
+
+        if print_messages {
+            println!("Opening interface 0x{:02x}", selected_interface);
+            println!("Read address 0x{:02x}", selected_read_address);
+            println!("Write address 0x{:02x}", selected_write_address);
+        }
+
+        return Ok(LibusbTransport {
+            handle: device.open()?,
+            interface: selected_interface,
+            read_address: selected_read_address,
+            detached_kernel_driver: false
+        });
+    }
+}
+
+
+impl<'a> Transport for LibusbTransport<'a> {
+
+    /**
+     * Writes 7 byte packets to 0x0210
+     */
+    fn send_short(&mut self, msg: &[u8; 7]) -> io::Result<()> {
+        self.handle.write_control(0x21, 9, 0x0210, 1, msg, Duration::from_secs(1)).map_err(usb_error)?;
+        Ok(())
+    }
+
+    /**
+     * Writes 20 byte packets to 0x0211
+     */
+    fn send_long(&mut self, msg: &[u8; 20]) -> io::Result<()> {
+        self.handle.write_control(0x21, 9, 0x0211, 1, msg, Duration::from_secs(1)).map_err(usb_error)?;
+        Ok(())
+    }
+
+    fn read_report(&mut self, out: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        match self.handle.read_interrupt(self.read_address, out, timeout) {
+            Ok(len) => Ok(len),
+            Err(libusb::Error::Timeout) => Ok(0),
+            Err(err) => Err(usb_error(err))
+        }
+    }
+
+    /**
+     * Remembers whether the kernel driver was bound, so release can give it back
+     */
+    fn claim(&mut self) -> io::Result<()> {
+        if self.handle.kernel_driver_active(self.interface).unwrap_or(false) {
+            self.handle.detach_kernel_driver(self.interface).map_err(usb_error)?;
+            self.detached_kernel_driver = true;
+        }
+        self.handle.claim_interface(self.interface).map_err(usb_error)
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        let result = self.handle.release_interface(self.interface).map_err(usb_error);
+
+        // reattach even if the release failed, otherwise the mouse stops moving
+        if self.detached_kernel_driver {
+            self.handle.attach_kernel_driver(self.interface).map_err(usb_error)?;
+            self.detached_kernel_driver = false;
+        }
+        return result;
+    }
+}
+
+
+/**
+ * A transport that needs no device, for tests
+ */
+#[cfg(test)]
+pub mod mock {
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::io;
+    use std::rc::Rc;
+    use std::time::Duration;
+    use super::Transport;
+
+    /**
+     * Hands out canned reports in order and keeps everything sent
+     * once the reports run out every read times out straight away
+     * claims and releases are shared so they can be checked after the transport is dropped
+     */
+    pub struct MockTransport {
+        pub sent: Vec<Vec<u8>>,
+        pub reports: VecDeque<Vec<u8>>,
+        pub claims: Rc<Cell<usize>>,
+        pub releases: Rc<Cell<usize>>,
+        pub fail_claim: bool
+    }
+
+
+    impl MockTransport {
+        pub fn new(reports: Vec<Vec<u8>>) -> MockTransport {
+            MockTransport {
+                sent: Vec::new(),
+                reports: reports.into_iter().collect(),
+                claims: Rc::new(Cell::new(0)),
+                releases: Rc::new(Cell::new(0)),
+                fail_claim: false
+            }
+        }
+    }
+
+
+    impl Transport for MockTransport {
+        fn send_short(&mut self, msg: &[u8; 7]) -> io::Result<()> {
+            self.sent.push(msg.to_vec());
+            Ok(())
+        }
+
+        fn send_long(&mut self, msg: &[u8; 20]) -> io::Result<()> {
+            self.sent.push(msg.to_vec());
+            Ok(())
+        }
+
+        fn read_report(&mut self, out: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+            match self.reports.pop_front() {
+                Some(report) => {
+                    out[..report.len()].copy_from_slice(&report);
+                    Ok(report.len())
+                },
+                None => Ok(0)
+            }
+        }
+
+        fn claim(&mut self) -> io::Result<()> {
+            self.claims.set(self.claims.get() + 1);
+            if self.fail_claim {
+                return Err(io::Error::new(io::ErrorKind::Other, "interface busy"));
+            }
+            Ok(())
+        }
+
+        fn release(&mut self) -> io::Result<()> {
+            self.releases.set(self.releases.get() + 1);
+            Ok(())
+        }
+    }
+}